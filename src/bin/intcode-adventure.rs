@@ -0,0 +1,52 @@
+//! Plays a text-adventure Intcode program in the terminal, with save slots,
+//! undo and scripted commands. Type `!help` once it starts. Save slots are
+//! written to the current directory, or the one given with `--saves`.
+//!
+//! Usage: intcode-adventure <program.txt> [--saves dir] [--transcript file.txt]
+
+use aoc_2019::intcode::adventure::Adventure;
+use aoc_2019::intcode::{load_program, VM};
+use std::fs::File;
+use std::io::{self, Write};
+use std::{env, process};
+
+const USAGE: &str = "usage: intcode-adventure <program.txt> [--saves dir] [--transcript file.txt]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some((path, flags)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    };
+
+    let (mut saves, mut transcript) = (".", None);
+    for pair in flags.chunks(2) {
+        match pair {
+            [flag, dir] if flag == "--saves" => saves = dir,
+            [flag, file] if flag == "--transcript" => transcript = Some(file),
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(1);
+            }
+        }
+    }
+
+    let program = load_program(path).unwrap_or_else(|x| {
+        eprintln!("{}", x);
+        process::exit(1);
+    });
+
+    let mut transcript: Box<dyn Write> = match transcript {
+        Some(path) => Box::new(File::create(path).unwrap_or_else(|x| {
+            eprintln!("can't create {}: {}", path, x);
+            process::exit(1);
+        })),
+        None => Box::new(io::sink()),
+    };
+
+    let mut adventure = Adventure::new(VM::new(&program), saves);
+    if let Err(x) = adventure.repl(io::stdin().lock(), &mut io::stdout(), &mut transcript) {
+        eprintln!("{}", x);
+        process::exit(1);
+    }
+}
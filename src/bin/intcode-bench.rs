@@ -0,0 +1,74 @@
+//! Times an Intcode program, run to completion over and over, both from a
+//! fresh VM and from a clone of one whose instructions were decoded up front.
+//! Build with `--release` for meaningful numbers.
+//!
+//! Usage: intcode-bench <program.txt> [runs] [input ...]
+
+use aoc_2019::intcode::{load_program, VmError, VM};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use std::{env, process};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(path) = args.first() else {
+        eprintln!("usage: intcode-bench <program.txt> [runs] [input ...]");
+        process::exit(1);
+    };
+    let runs = args.get(1).and_then(|x| x.parse().ok()).unwrap_or(100);
+    let input: Vec<i64> = args
+        .iter()
+        .skip(2)
+        .map(|x| {
+            x.parse().unwrap_or_else(|_| {
+                eprintln!("invalid input value: {}", x);
+                process::exit(1);
+            })
+        })
+        .collect();
+
+    let program = load_program(path).unwrap_or_else(|x| {
+        eprintln!("{}", x);
+        process::exit(1);
+    });
+
+    let predecoded = VM::new(&program).predecoded();
+    let timings = [
+        ("fresh", time(runs, || run(VM::new(&program), &input))),
+        ("predecoded", time(runs, || run(predecoded.clone(), &input))),
+    ];
+
+    for (name, timing) in timings {
+        match timing {
+            Ok((best, median)) => println!(
+                "{:<12} best {:>10.1?}  median {:>10.1?}  ({} runs)",
+                name, best, median, runs
+            ),
+            Err(x) => {
+                eprintln!("{}", x);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn run(mut vm: VM, input: &[i64]) -> Result<(), VmError> {
+    vm.run(&mut VecDeque::from(input.to_vec()), &mut vec![])
+        .map(|_| ())
+}
+
+/// Returns the best and the median time of `runs` calls to `f`.
+fn time(
+    runs: usize,
+    mut f: impl FnMut() -> Result<(), VmError>,
+) -> Result<(Duration, Duration), VmError> {
+    let mut times = Vec::with_capacity(runs);
+    for _ in 0..runs.max(1) {
+        let start = Instant::now();
+        f()?;
+        times.push(start.elapsed());
+    }
+
+    times.sort_unstable();
+    Ok((times[0], times[times.len() / 2]))
+}
@@ -0,0 +1,34 @@
+//! Builds the control-flow graph of an Intcode program and prints it in
+//! Graphviz's DOT language, along with a summary on stderr.
+//!
+//! Usage: intcode-cfg <program.txt> | dot -Tsvg > cfg.svg
+
+use aoc_2019::intcode::{control_flow_graph, load_program};
+use std::{env, process};
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: intcode-cfg <program.txt>");
+        process::exit(1);
+    };
+
+    let program = load_program(&path).unwrap_or_else(|x| {
+        eprintln!("{}", x);
+        process::exit(1);
+    });
+
+    let cfg = control_flow_graph(&program);
+    print!("{}", cfg.to_dot());
+
+    let dynamic = cfg.blocks.iter().filter(|x| x.dynamic_exit).count();
+    let cells: Vec<String> = cfg.self_modifying.iter().map(|x| x.to_string()).collect();
+    eprintln!(
+        "{} blocks, {} edges, {} dynamic jumps",
+        cfg.blocks.len(),
+        cfg.edges.len(),
+        dynamic
+    );
+    if !cells.is_empty() {
+        eprintln!("self-modifying cells: {}", cells.join(", "));
+    }
+}
@@ -0,0 +1,32 @@
+//! Loads an Intcode program and opens the step debugger on it.
+//!
+//! Usage: intcode-debug <program.txt>
+
+use aoc_2019::intcode::debugger::Debugger;
+use aoc_2019::intcode::{lint, load_program, VM};
+use std::{env, io, process};
+
+/// How many instructions `back` and `rewind` can undo.
+const HISTORY: usize = 1_000_000;
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: intcode-debug <program.txt>");
+        process::exit(1);
+    };
+
+    let program = load_program(&path).unwrap_or_else(|x| {
+        eprintln!("{}", x);
+        process::exit(1);
+    });
+
+    for warning in lint(&program) {
+        eprintln!("warning: {}", warning);
+    }
+
+    let mut debugger = Debugger::new(VM::new(&program).with_history(HISTORY));
+    if let Err(x) = debugger.repl(io::stdin().lock(), &mut io::stdout()) {
+        eprintln!("{}", x);
+        process::exit(1);
+    }
+}
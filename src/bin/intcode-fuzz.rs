@@ -0,0 +1,29 @@
+//! Runs random Intcode programs on the VM and on the reference interpreter,
+//! and prints the smallest program they disagree on, if any.
+//!
+//! Usage: intcode-fuzz [runs] [seed]
+
+use aoc_2019::intcode::fuzz;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, process};
+
+fn main() {
+    let runs = env::args()
+        .nth(1)
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(100_000);
+    let seed = env::args()
+        .nth(2)
+        .and_then(|x| x.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs())
+        });
+
+    eprintln!("running {} cases with seed {}", runs, seed);
+    if let Err(mismatch) = fuzz::fuzz(seed, runs) {
+        println!("{}", mismatch);
+        process::exit(1);
+    }
+}
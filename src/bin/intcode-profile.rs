@@ -0,0 +1,46 @@
+//! Runs an Intcode program with the profiler on and prints where it spent its
+//! time. The program reads its input from stdin and writes its output to
+//! stdout, one number per line or, with `--ascii`, as text; the report goes to
+//! stderr.
+//!
+//! Usage: intcode-profile [--ascii] <program.txt> [top]
+
+use aoc_2019::intcode::{load_program, AsciiInput, AsciiOutput, TextInput, TextOutput, VM};
+use std::{env, io, process};
+
+fn main() {
+    let ascii = env::args().any(|x| x == "--ascii");
+    let args: Vec<String> = env::args().skip(1).filter(|x| x != "--ascii").collect();
+
+    let Some(path) = args.first() else {
+        eprintln!("usage: intcode-profile [--ascii] <program.txt> [top]");
+        process::exit(1);
+    };
+    let top = args.get(1).and_then(|x| x.parse().ok()).unwrap_or(10);
+
+    let program = load_program(path).unwrap_or_else(|x| {
+        eprintln!("{}", x);
+        process::exit(1);
+    });
+
+    let mut vm = VM::new(&program).with_profiler();
+    let result = match ascii {
+        true => vm.run(
+            &mut AsciiInput::new(io::stdin().lock()),
+            &mut AsciiOutput(io::stdout()),
+        ),
+        false => vm.run(
+            &mut TextInput(io::stdin().lock()),
+            &mut TextOutput(io::stdout()),
+        ),
+    };
+
+    if let Some(profiler) = vm.profiler() {
+        eprint!("{}", profiler.report(top));
+    }
+
+    if let Err(x) = result {
+        eprintln!("{}", x);
+        process::exit(1);
+    }
+}
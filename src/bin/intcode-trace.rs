@@ -0,0 +1,85 @@
+//! Records, prints and compares Intcode execution traces.
+//!
+//! Usage:
+//!   intcode-trace record <program.txt> <out.trace>   (input read from stdin)
+//!   intcode-trace show <file.trace>
+//!   intcode-trace diff <a.trace> <b.trace>
+//!   intcode-trace replay <file.trace> <program.txt>
+
+use aoc_2019::intcode::trace::Trace;
+use aoc_2019::intcode::{load_program, TextInput, TextOutput, VM};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::{env, process};
+
+const USAGE: &str = "usage: intcode-trace record <program.txt> <out.trace>
+       intcode-trace show <file.trace>
+       intcode-trace diff <a.trace> <b.trace>
+       intcode-trace replay <file.trace> <program.txt>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+
+    match args.as_slice() {
+        ["record", program, out] => {
+            let mut vm = VM::new(&read_program(program)).with_trace();
+            let result = vm.run(
+                &mut TextInput(io::stdin().lock()),
+                &mut TextOutput(io::stdout()),
+            );
+
+            let trace = vm.take_trace().unwrap_or_default();
+            let mut file = BufWriter::new(File::create(out).unwrap_or_else(|x| fail(out, x)));
+            trace.write_to(&mut file).unwrap_or_else(|x| fail(out, x));
+
+            if let Err(x) = result {
+                eprintln!("{}", x);
+                process::exit(1);
+            }
+        }
+        ["show", path] => {
+            for step in read_trace(path).steps {
+                println!("{}", step);
+            }
+        }
+        ["diff", left, right] => {
+            if let Some(divergence) = read_trace(left).diff(&read_trace(right)) {
+                print!("{}", divergence);
+                process::exit(1);
+            }
+        }
+        ["replay", path, program] => match read_trace(path).replay(&read_program(program)) {
+            Ok(None) => {}
+            Ok(Some(divergence)) => {
+                print!("{}", divergence);
+                process::exit(1);
+            }
+            Err(x) => {
+                eprintln!("{}", x);
+                process::exit(1);
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    }
+}
+
+fn read_program(path: &str) -> Vec<i64> {
+    load_program(path).unwrap_or_else(|x| {
+        eprintln!("{}", x);
+        process::exit(1);
+    })
+}
+
+fn read_trace(path: &str) -> Trace {
+    let file = File::open(path).unwrap_or_else(|x| fail(path, x));
+    Trace::read_from(&mut BufReader::new(file)).unwrap_or_else(|x| fail(path, x))
+}
+
+fn fail(path: &str, error: io::Error) -> ! {
+    eprintln!("{}: {}", path, error);
+    process::exit(1);
+}
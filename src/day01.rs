@@ -12,12 +12,12 @@ fn fuel(mass: u64) -> u64 {
 
 #[aoc(day1, part1)]
 pub fn solve_part1(input: &[u64]) -> u64 {
-    input.into_iter().fold(0, |total, mass| total + fuel(*mass))
+    input.iter().fold(0, |total, mass| total + fuel(*mass))
 }
 
 fn fuel2(mass: u64) -> u64 {
     let res = fuel(mass);
-    if res <= 0 {
+    if res == 0 {
         res
     } else {
         res + fuel2(res)
@@ -26,9 +26,7 @@ fn fuel2(mass: u64) -> u64 {
 
 #[aoc(day1, part2)]
 pub fn solve_part2(input: &[u64]) -> u64 {
-    input
-        .into_iter()
-        .fold(0, |total, mass| total + fuel2(*mass))
+    input.iter().fold(0, |total, mass| total + fuel2(*mass))
 }
 
 #[cfg(test)]
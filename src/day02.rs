@@ -1,73 +1,101 @@
+use crate::intcode::symbolic::{self, Polynomial};
+use crate::intcode::{parse_program, Profile, VmError, VM};
 use aoc_runner_derive::aoc;
 use aoc_runner_derive::aoc_generator;
+use std::collections::VecDeque;
 
 #[aoc_generator(day2)]
-pub fn parse_input(input: &str) -> Vec<u64> {
-    input
-        .split(",")
-        .map(|x| x.parse::<u64>().unwrap())
-        .collect()
+pub fn parse_input(input: &str) -> Vec<i64> {
+    parse_program(input).unwrap()
 }
 
-fn run(src: &[u64]) -> u64 {
-    let mut res = src.to_owned();
-    let mut pc: usize = 0;
-
-    while pc < res.len() {
-        match res[pc] {
-            99 => break,
-            1 => {
-                let ix = res[pc + 1] as usize;
-                let iy = res[pc + 2] as usize;
-                let target = res[pc + 3] as usize;
-                res[target] = res[ix] + res[iy];
-                pc += 4;
-            }
-            2 => {
-                let ix = res[pc + 1] as usize;
-                let iy = res[pc + 2] as usize;
-                let target = res[pc + 3] as usize;
-                res[target] = res[ix] * res[iy];
-                pc += 4;
-            }
-            _ => unreachable!("unknown opcode"),
-        }
-    }
-
-    res[0]
+fn day2_vm(src: &[i64]) -> VM {
+    VM::new(src).with_profile(Profile::Day2)
 }
 
-fn run_with_input(input: &[u64], noun: u64, verb: u64) -> u64 {
-    let mut src = input.to_owned();
-    src[1] = noun;
-    src[2] = verb;
-
-    run(&src)
+fn run_with_noun_verb(vm: VM, noun: i64, verb: i64) -> Result<i64, VmError> {
+    vm.with_noun_verb(noun, verb)?
+        .run(&mut VecDeque::new(), &mut vec![])
 }
 
 #[aoc(day2, part1)]
-pub fn solve_part1(input: &[u64]) -> u64 {
-    run_with_input(input, 12, 2)
+pub fn solve_part1(input: &[i64]) -> Result<i64, VmError> {
+    run_with_noun_verb(day2_vm(input), 12, 2)
 }
 
+const TARGET: i64 = 19690720;
+const NOUNS_AND_VERBS: std::ops::RangeInclusive<i64> = 0..=99;
+
+/// Finds the noun and verb that make the program output [`TARGET`].
+///
+/// The program is first run symbolically, with the noun and verb as unknowns,
+/// and the candidates come from solving the resulting polynomial. When that
+/// isn't possible every pair is tried. Either way, candidates are checked by
+/// actually running the program, which catches values that make it fail.
 #[aoc(day2, part2)]
-pub fn solve_part2(input: &[u64]) -> u64 {
-    for noun in 0..=99 {
-        for verb in 0..=99 {
-            let output = run_with_input(input, noun, verb);
-            if output == 19690720 {
-                return noun * 100 + verb;
+pub fn solve_part2(input: &[i64]) -> Option<i64> {
+    let vm = day2_vm(input).predecoded();
+
+    let mut candidates: Box<dyn Iterator<Item = (i64, i64)>> =
+        match symbolic::execute(input, &[1, 2], 0) {
+            Ok(output) => Box::new(solve(&output, TARGET).into_iter()),
+            Err(_) => Box::new(
+                NOUNS_AND_VERBS.flat_map(|noun| NOUNS_AND_VERBS.map(move |verb| (noun, verb))),
+            ),
+        };
+
+    candidates
+        .find(|(noun, verb)| run_with_noun_verb(vm.clone(), *noun, *verb) == Ok(TARGET))
+        .map(|(noun, verb)| noun * 100 + verb)
+}
+
+/// The pairs of noun and verb for which `output`, a polynomial in the noun
+/// (`x0`) and verb (`x1`), equals `target`, in the order brute force would
+/// find them.
+fn solve(output: &Polynomial, target: i64) -> Vec<(i64, i64)> {
+    let target = i128::from(target);
+    let mut solutions = vec![];
+
+    for noun in NOUNS_AND_VERBS {
+        let Some(row) = output.substitute(0, noun.into()) else {
+            continue;
+        };
+
+        match row.degree_in(1) {
+            0 if row.coefficient(&[]) == target => {
+                solutions.extend(NOUNS_AND_VERBS.map(|verb| (noun, verb)));
             }
+            0 => {}
+            1 => {
+                let (a, b) = (row.coefficient(&[0, 1]), row.coefficient(&[]));
+                if (target - b) % a == 0 {
+                    let verb = i64::try_from((target - b) / a).ok();
+                    if let Some(verb) = verb.filter(|x| NOUNS_AND_VERBS.contains(x)) {
+                        solutions.push((noun, verb));
+                    }
+                }
+            }
+            _ => solutions.extend(
+                NOUNS_AND_VERBS
+                    .filter(|verb| row.eval(&[0, (*verb).into()]) == Some(target))
+                    .map(|verb| (noun, verb)),
+            ),
         }
     }
 
-    0
+    solutions
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod tests {
     use super::*;
 
+    fn run(src: &[i64]) -> i64 {
+        let mut vm = VM::new(src).with_profile(Profile::Day2);
+        vm.run(&mut VecDeque::new(), &mut vec![]).unwrap()
+    }
+
     #[test]
     fn test_run_example() {
         assert_eq!(run(&vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]), 3500)
@@ -80,4 +108,34 @@ mod tests {
         assert_eq!(run(&vec![2, 4, 4, 5, 99, 0]), 2);
         assert_eq!(run(&vec![1, 1, 1, 4, 99, 5, 6, 0, 99]), 30);
     }
+
+    #[test]
+    fn test_solve() {
+        // 3 * noun^2 + verb
+        let program = [1, 0, 0, 3, 2, 1, 1, 0, 2, 0, 17, 0, 1, 0, 2, 0, 99, 3];
+        let output = symbolic::execute(&program, &[1, 2], 0).unwrap();
+        assert_eq!(output.to_string(), "3*x0^2 + x1");
+        assert_eq!(solve(&output, 300), vec![(9, 57), (10, 0)]);
+
+        // noun * verb
+        let program = [1, 0, 0, 3, 2, 1, 2, 0, 99];
+        let output = symbolic::execute(&program, &[1, 2], 0).unwrap();
+        assert_eq!(solve(&output, 0).len(), 199);
+        assert_eq!(solve(&output, 2021), vec![(43, 47), (47, 43)]);
+    }
+
+    #[test]
+    fn test_solve_part2_without_solution() {
+        // the output is noun + verb, which can't reach the target
+        assert_eq!(solve_part2(&[1, 0, 0, 3, 1, 1, 2, 0, 99]), None);
+        // the noun is executed as an instruction, so this one is brute forced
+        assert_eq!(solve_part2(&[1, 0, 0, 3, 1, 1, 9, 8, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_run_with_noun_verb() {
+        let vm = day2_vm(&[1, 0, 0, 0, 99, 7, 8]);
+        assert_eq!(run_with_noun_verb(vm.clone(), 5, 6), Ok(15));
+        assert!(run_with_noun_verb(vm, 50, 6).is_err());
+    }
 }
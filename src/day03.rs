@@ -39,7 +39,7 @@ impl Chunk {
 type Segment = (Vec2, Vec2);
 
 fn to_segments(chunks: &[Chunk]) -> Vec<Segment> {
-    let points: Vec<Vec2> = chunks.into_iter().fold(vec![(0, 0)], |mut acc, chunk| {
+    let points: Vec<Vec2> = chunks.iter().fold(vec![(0, 0)], |mut acc, chunk| {
         let last = acc.last().unwrap().to_owned();
         let current = (last.0 + chunk.delta().0, last.1 + chunk.delta().1);
         acc.push(current);
@@ -113,11 +113,7 @@ pub fn solve_part1(input: &(Vec<Chunk>, Vec<Chunk>)) -> u64 {
         .filter(|p| *p != (0, 0))
         .collect();
 
-    intersections
-        .into_iter()
-        .map(|p| distance(p))
-        .min()
-        .unwrap()
+    intersections.into_iter().map(distance).min().unwrap()
 }
 
 #[aoc(day3, part2)]
@@ -147,7 +143,7 @@ pub fn solve_part2(input: &(Vec<Chunk>, Vec<Chunk>)) -> u64 {
         .into_iter()
         .map(|(_, steps)| steps)
         .min()
-        .unwrap() as u64
+        .unwrap()
 }
 
 #[cfg(test)]
@@ -57,7 +57,7 @@ impl Iterator for RepeatingChunker {
         let repeating: Vec<u64> = self.digits[self.i..]
             .iter()
             .take_while(|x| **x == value)
-            .map(|x| *x)
+            .copied()
             .collect();
 
         self.i += repeating.len();
@@ -108,6 +108,7 @@ pub fn solve_part2(input: &(u64, u64)) -> u64 {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -9,7 +9,6 @@ type Orbit = (String, String);
 pub fn parse_input(input: &str) -> Vec<Orbit> {
     input
         .lines()
-        .into_iter()
         .map(|line| {
             let mut chunks = line.split(")");
             (
@@ -57,7 +56,7 @@ impl OrbitalMap {
     }
 
     fn find_path(&self, from: &str, to: &str) -> Option<Vec<String>> {
-        self.find_subpath(from, to, &vec![])
+        self.find_subpath(from, to, &[])
     }
 
     fn find_subpath(&self, from: &str, to: &str, visited: &[String]) -> Option<Vec<String>> {
@@ -69,7 +68,7 @@ impl OrbitalMap {
             return Some(path);
         }
 
-        let upward = current.ancestors(&self.tree).skip(1).next();
+        let upward = current.ancestors(&self.tree).nth(1);
         let downward = current.children(&self.tree);
 
         let mut potential: Vec<NodeId> = match upward {
@@ -80,18 +79,19 @@ impl OrbitalMap {
 
         let found: Vec<Vec<String>> = potential
             .iter()
-            .map(|node_id| {
+            .filter_map(|node_id| {
                 if !visited.contains(&self.value_for_id(*node_id)) {
-                    let new_visited = vec![visited, &path].concat();
+                    let new_visited = [visited, &path].concat();
                     self.find_subpath(&self.value_for_id(*node_id), to, &new_visited)
                 } else {
                     None
                 }
             })
-            .flatten()
             .collect();
 
-        found.get(0).map(|subpath| [path, subpath.clone()].concat())
+        found
+            .first()
+            .map(|subpath| [path, subpath.clone()].concat())
     }
 }
 
@@ -127,6 +127,7 @@ pub fn solve_part2(input: &[Orbit]) -> Option<u64> {
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_static_lifetimes, clippy::useless_vec)]
 mod tests {
     use super::*;
 
@@ -0,0 +1,53 @@
+//! Intcode virtual machine shared by every puzzle that runs Intcode programs,
+//! along with tools to write, inspect and debug those programs.
+
+pub mod adventure;
+mod amplifier;
+mod ascii;
+pub mod asm;
+mod cfg;
+pub mod debugger;
+mod device;
+mod disasm;
+mod error;
+mod extension;
+pub mod fuzz;
+mod history;
+mod instruction;
+mod io;
+mod lint;
+mod memory;
+pub mod profiler;
+pub mod snapshot;
+pub mod symbolic;
+pub mod trace;
+mod vm;
+
+pub use amplifier::{max_thruster_signal, AmplifierError, Amplifiers};
+pub use ascii::{Ascii, Printout};
+pub use cfg::{control_flow_graph, BasicBlock, ControlFlowGraph, Edge, EdgeKind};
+pub use device::Device;
+pub use disasm::{disassemble, Listing, ListingLine};
+pub use error::VmError;
+pub use extension::{ExtensionContext, OpcodeExtension};
+pub use instruction::{Instruction, Opcode, ParamMode};
+pub use io::{
+    AsciiInput, AsciiOutput, IntcodeInput, IntcodeOutput, IterInput, TextInput, TextOutput,
+};
+pub use lint::{lint, LintWarning};
+pub use vm::{ArithmeticPolicy, Profile, RunState, VM};
+
+use std::fs;
+use std::num::ParseIntError;
+
+/// Parses a program written as comma-separated integers.
+pub fn parse_program(src: &str) -> Result<Vec<i64>, ParseIntError> {
+    src.trim().split(',').map(|x| x.trim().parse()).collect()
+}
+
+/// Reads and parses the program in the file at `path`, failing with a message
+/// that names the file.
+pub fn load_program(path: &str) -> Result<Vec<i64>, String> {
+    let src = fs::read_to_string(path).map_err(|x| format!("can't read {}: {}", path, x))?;
+    parse_program(&src).map_err(|x| format!("invalid program in {}: {}", path, x))
+}
@@ -0,0 +1,511 @@
+//! Plays text-adventure Intcode programs, which talk in ASCII, with save
+//! slots and undo on top.
+//!
+//! Lines are sent to the program as they are, except for the ones starting
+//! with `!`, which are meta-commands. Type `!help` for the list.
+//!
+//! Each save slot is kept in the save directory as three files, so that a game
+//! can be picked up again after quitting: `<name>.snapshot` holds the VM, as
+//! written by [`Snapshot::write_to`], `<name>.history` the commands sent so
+//! far, one per line, and `<name>.screen` what the game last printed.
+
+use super::snapshot::Snapshot;
+use super::{Ascii, Printout, VM};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::PathBuf;
+
+const HELP: &str = "\
+meta-commands:
+  !save <name>          save the game in a slot
+  !load <name>          go back to a saved game
+  !saves                list the save slots
+  !undo                 take back the last command sent to the game
+  !history              list the commands sent to the game so far
+  !auto <script.txt>    run each line of a file as if it was typed
+  !help                 show this help
+  !quit                 leave the game";
+
+/// How many commands `!undo` can take back.
+const UNDO_LIMIT: usize = 1000;
+
+/// Everything needed to go back to a point in the game.
+#[derive(Debug, PartialEq, Clone)]
+struct Checkpoint {
+    snapshot: Snapshot,
+    history: Vec<String>,
+    screen: String,
+    halted: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Adventure {
+    ascii: Ascii,
+    /// What the program printed in response to the last command, shown again
+    /// after going back to a checkpoint.
+    screen: String,
+    halted: bool,
+    history: Vec<String>,
+    undo: VecDeque<Checkpoint>,
+    save_dir: PathBuf,
+}
+
+impl Adventure {
+    /// Plays `vm`, keeping save slots in `save_dir`, which is created on the
+    /// first save.
+    pub fn new(vm: VM, save_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            ascii: Ascii::new(vm),
+            screen: String::new(),
+            halted: false,
+            history: vec![],
+            undo: VecDeque::new(),
+            save_dir: save_dir.into(),
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        self.ascii.vm()
+    }
+
+    /// Commands sent to the program so far, leaving out the ones undone.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Runs the program until it asks for the first command.
+    pub fn start(&mut self, output: &mut impl io::Write) -> Result<(), String> {
+        let printout = self.ascii.run().map_err(|x| x.to_string())?;
+        self.show(printout, output)
+    }
+
+    /// Starts the program and then reads lines from `input` until it's
+    /// exhausted or the user quits. Typed lines and everything written to
+    /// `output` are copied to `transcript`.
+    pub fn repl(
+        &mut self,
+        input: impl io::BufRead,
+        output: &mut impl io::Write,
+        transcript: &mut impl io::Write,
+    ) -> io::Result<()> {
+        let mut screen = vec![];
+        if let Err(message) = self.start(&mut screen) {
+            writeln!(screen, "error: {}", message)?;
+        }
+        tee(&screen, output, transcript)?;
+
+        for line in input.lines() {
+            let line = line?;
+            writeln!(transcript, "{}", line)?;
+
+            let mut screen = vec![];
+            let quit = match self.execute(&line, &mut screen) {
+                Ok(quit) => quit,
+                Err(message) => {
+                    writeln!(screen, "error: {}", message)?;
+                    false
+                }
+            };
+            tee(&screen, output, transcript)?;
+            if quit {
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends `line` to the program, or runs it if it's a meta-command, writing
+    /// the results to `output`. Returns whether the user asked to quit.
+    pub fn execute(&mut self, line: &str, output: &mut impl io::Write) -> Result<bool, String> {
+        let Some(command) = line.trim().strip_prefix('!') else {
+            self.send(line, output)?;
+            return Ok(false);
+        };
+
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let io_error = |x: io::Error| format!("{}", x);
+
+        match name {
+            "save" => {
+                let slot = parse_slot(&args)?;
+                self.write_slot(slot)
+                    .map_err(|x| format!("can't save {}: {}", slot, x))?;
+                writeln!(output, "saved {}", slot).map_err(io_error)?;
+            }
+            "load" => {
+                let slot = parse_slot(&args)?;
+                let checkpoint = self.read_slot(slot).map_err(|x| match x.kind() {
+                    io::ErrorKind::NotFound => format!("no game saved in {}", slot),
+                    _ => format!("can't load {}: {}", slot, x),
+                })?;
+                self.push_undo(self.checkpoint());
+                self.restore(checkpoint)?;
+                writeln!(output, "loaded {}", slot).map_err(io_error)?;
+                write!(output, "{}", self.screen).map_err(io_error)?;
+            }
+            "saves" => {
+                for name in self.slot_names().map_err(io_error)? {
+                    let commands = match fs::read_to_string(self.slot_file(&name, "history")) {
+                        Ok(history) => history.lines().count().to_string(),
+                        Err(_) => "?".to_string(),
+                    };
+                    writeln!(output, "  {:<16} {} commands", name, commands).map_err(io_error)?;
+                }
+            }
+            "undo" => {
+                let checkpoint = self.undo.pop_back().ok_or("nothing to undo")?;
+                self.restore(checkpoint)?;
+                write!(output, "{}", self.screen).map_err(io_error)?;
+            }
+            "history" => {
+                for (i, command) in self.history.iter().enumerate() {
+                    writeln!(output, "{:>4}  {}", i + 1, command).map_err(io_error)?;
+                }
+            }
+            "auto" => {
+                let path = args.first().ok_or("missing script")?;
+                let script =
+                    fs::read_to_string(path).map_err(|x| format!("can't read {}: {}", path, x))?;
+                return self.run_script(&script, output);
+            }
+            "help" => writeln!(output, "{}", HELP).map_err(io_error)?,
+            "q" | "quit" => return Ok(true),
+            _ => return Err(format!("unknown command: !{} (try !help)", name)),
+        }
+
+        Ok(false)
+    }
+
+    /// Runs each line of `script`, echoing it first, and stops at the first
+    /// one that fails. Returns whether the script asked to quit.
+    pub fn run_script(
+        &mut self,
+        script: &str,
+        output: &mut impl io::Write,
+    ) -> Result<bool, String> {
+        for line in script.lines().filter(|x| !x.trim().is_empty()) {
+            if line.trim().starts_with("!auto") {
+                return Err("scripts can't run other scripts".to_string());
+            }
+            writeln!(output, "{}", line).map_err(|x| format!("{}", x))?;
+            if self.execute(line, output)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Sends a line to the program and runs it until it asks for the next
+    /// one. If the program fails, the game goes back to how it was before.
+    fn send(&mut self, line: &str, output: &mut impl io::Write) -> Result<(), String> {
+        if self.halted {
+            return Err("the program has halted, use !undo or !load to go back".to_string());
+        }
+
+        let checkpoint = self.checkpoint();
+        self.ascii.send(line).map_err(|x| x.to_string())?;
+        let printout = match self.ascii.run() {
+            Ok(printout) => printout,
+            Err(error) => {
+                self.restore(checkpoint)?;
+                return Err(format!("{}, going back to before {:?}", error, line));
+            }
+        };
+
+        self.push_undo(checkpoint);
+        self.history.push(line.to_string());
+        self.show(printout, output)
+    }
+
+    fn show(&mut self, printout: Printout, output: &mut impl io::Write) -> Result<(), String> {
+        let io_error = |x: io::Error| format!("{}", x);
+
+        write!(output, "{}", printout.text).map_err(io_error)?;
+        for answer in &printout.answers {
+            writeln!(output, "answer: {}", answer).map_err(io_error)?;
+        }
+        if printout.halted {
+            writeln!(output, "the program halted").map_err(io_error)?;
+        }
+
+        self.screen = printout.text;
+        self.halted = printout.halted;
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            snapshot: self.ascii.vm().snapshot(),
+            history: self.history.clone(),
+            screen: self.screen.clone(),
+            halted: self.halted,
+        }
+    }
+
+    fn push_undo(&mut self, checkpoint: Checkpoint) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(checkpoint);
+    }
+
+    fn slot_file(&self, slot: &str, extension: &str) -> PathBuf {
+        self.save_dir.join(slot).with_extension(extension)
+    }
+
+    fn write_slot(&self, slot: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.save_dir)?;
+
+        let mut file = File::create(self.slot_file(slot, "snapshot"))?;
+        self.vm().snapshot().write_to(&mut file)?;
+        let history: String = self.history.iter().map(|x| format!("{}\n", x)).collect();
+        fs::write(self.slot_file(slot, "history"), history)?;
+        fs::write(self.slot_file(slot, "screen"), &self.screen)
+    }
+
+    /// Reads a slot back. Whether the game had halted isn't saved: a halted
+    /// game halts again as soon as it's sent a command.
+    fn read_slot(&self, slot: &str) -> io::Result<Checkpoint> {
+        let file = File::open(self.slot_file(slot, "snapshot"))?;
+        Ok(Checkpoint {
+            snapshot: Snapshot::read_from(BufReader::new(file))?,
+            history: fs::read_to_string(self.slot_file(slot, "history"))?
+                .lines()
+                .map(|x| x.to_string())
+                .collect(),
+            screen: fs::read_to_string(self.slot_file(slot, "screen"))?,
+            halted: false,
+        })
+    }
+
+    /// Names of the slots in the save directory, sorted.
+    fn slot_names(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.save_dir) {
+            Ok(entries) => entries,
+            Err(x) if x.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(x) => return Err(x),
+        };
+
+        let mut names = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|x| x == "snapshot") {
+                if let Some(name) = path.file_stem().and_then(|x| x.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) -> Result<(), String> {
+        self.ascii
+            .vm_mut()
+            .restore(checkpoint.snapshot)
+            .map_err(|x| x.to_string())?;
+        self.history = checkpoint.history;
+        self.screen = checkpoint.screen;
+        self.halted = checkpoint.halted;
+        Ok(())
+    }
+}
+
+/// Slot names become file names, so they're kept to letters, digits, `-` and
+/// `_`.
+fn parse_slot<'a>(args: &[&'a str]) -> Result<&'a str, String> {
+    let slot = args
+        .first()
+        .copied()
+        .ok_or_else(|| "missing slot name".to_string())?;
+    if !slot
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!("invalid slot name: {}", slot));
+    }
+    Ok(slot)
+}
+
+fn tee(
+    text: &[u8],
+    output: &mut impl io::Write,
+    transcript: &mut impl io::Write,
+) -> io::Result<()> {
+    output.write_all(text)?;
+    output.flush()?;
+    transcript.write_all(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ArithmeticPolicy, Device, VmError};
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::sync::{Arc, Mutex};
+
+    // prints "?", echoes a line, and starts over until it has read 3 lines;
+    // then prints 1000 and halts
+    const PROGRAM: [i64; 29] = [
+        104, 63, 104, 10, 3, 100, 4, 100, 1008, 100, 10, 101, 1006, 101, 4, 1001, 102, 1, 102,
+        1008, 102, 3, 101, 1006, 101, 0, 104, 1000, 99,
+    ];
+
+    /// An empty directory for a test's save slots.
+    fn save_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("intcode-adventure-{}-{}", test, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn run(adventure: &mut Adventure, line: &str) -> String {
+        let mut output = vec![];
+        adventure.execute(line, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_save_load_and_undo() {
+        let dir = save_dir("undo");
+        let mut adventure = Adventure::new(VM::new(&PROGRAM), &dir);
+        adventure.start(&mut vec![]).unwrap();
+
+        assert_eq!(run(&mut adventure, "north"), "north\n?\n");
+        assert_eq!(run(&mut adventure, "!save hall"), "saved hall\n");
+        assert_eq!(run(&mut adventure, "east"), "east\n?\n");
+        assert_eq!(run(&mut adventure, "!undo"), "north\n?\n");
+        assert_eq!(adventure.history(), ["north"]);
+
+        assert_eq!(run(&mut adventure, "west"), "west\n?\n");
+        assert_eq!(
+            run(&mut adventure, "up"),
+            "up\nanswer: 1000\nthe program halted\n"
+        );
+        assert!(adventure.execute("down", &mut vec![]).is_err());
+
+        assert_eq!(run(&mut adventure, "!load hall"), "loaded hall\nnorth\n?\n");
+        assert_eq!(run(&mut adventure, "!history"), "   1  north\n");
+        assert_eq!(run(&mut adventure, "!undo"), "up\n");
+        assert_eq!(adventure.history(), ["north", "west", "up"]);
+        assert!(adventure.execute("!load attic", &mut vec![]).is_err());
+        assert!(adventure.execute("!save ../attic", &mut vec![]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_slots_outlive_the_game() {
+        let dir = save_dir("slots");
+        let mut adventure = Adventure::new(VM::new(&PROGRAM), &dir);
+        adventure.start(&mut vec![]).unwrap();
+        run(&mut adventure, "north");
+        run(&mut adventure, "east");
+        assert_eq!(run(&mut adventure, "!save hall"), "saved hall\n");
+        assert!(dir.join("hall.snapshot").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("hall.history")).unwrap(),
+            "north\neast\n"
+        );
+        drop(adventure);
+
+        let mut adventure = Adventure::new(VM::new(&PROGRAM), &dir);
+        adventure.start(&mut vec![]).unwrap();
+        assert_eq!(
+            run(&mut adventure, "!saves"),
+            "  hall             2 commands\n"
+        );
+        assert_eq!(run(&mut adventure, "!load hall"), "loaded hall\neast\n?\n");
+        assert_eq!(adventure.history(), ["north", "east"]);
+        assert_eq!(
+            run(&mut adventure, "up"),
+            "up\nanswer: 1000\nthe program halted\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A single cell that always reads as 7.
+    struct Seven;
+
+    impl Device for Seven {
+        fn read(&mut self, _: usize) -> io::Result<i64> {
+            Ok(7)
+        }
+
+        fn write(&mut self, _: usize, _: i64) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_undo_keeps_the_vm_config() {
+        let vm = VM::new(&PROGRAM)
+            .with_strict_decoding()
+            .with_arithmetic(ArithmeticPolicy::Wrapping)
+            .with_memory_limit(200)
+            .with_device(150..151, Arc::new(Mutex::new(Seven)))
+            .with_profiler();
+        let dir = save_dir("config");
+        let mut adventure = Adventure::new(vm, &dir);
+        adventure.start(&mut vec![]).unwrap();
+
+        run(&mut adventure, "north");
+        run(&mut adventure, "!save hall");
+        run(&mut adventure, "east");
+        run(&mut adventure, "!undo");
+        run(&mut adventure, "!load hall");
+
+        let mut vm = adventure.vm().clone();
+        assert_eq!(vm.snapshot().arithmetic, ArithmeticPolicy::Wrapping);
+        assert_eq!(vm.snapshot().memory_limit, Some(200));
+        assert!(vm.profiler().is_some());
+        assert_eq!(vm.read_mem(150), Ok(7));
+
+        let ip = vm.ip();
+        vm.write_mem(ip, 11101).unwrap();
+        assert!(matches!(vm.resume(), Err(VmError::ImmediateWrite { .. })));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_script_and_transcript() {
+        let dir = save_dir("script");
+        let mut adventure = Adventure::new(VM::new(&PROGRAM), &dir);
+        adventure.start(&mut vec![]).unwrap();
+
+        let mut output = vec![];
+        assert_eq!(
+            adventure.run_script("look\n\n!save a\n", &mut output),
+            Ok(false)
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "look\nlook\n?\n!save a\nsaved a\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut adventure = Adventure::new(VM::new(&PROGRAM), &dir);
+        let mut output = vec![];
+        let mut transcript = vec![];
+        adventure
+            .repl(
+                "look\n!nope\n!quit\ntake\n".as_bytes(),
+                &mut output,
+                &mut transcript,
+            )
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "?\nlook\n?\nerror: unknown command: !nope (try !help)\n"
+        );
+        assert_eq!(
+            String::from_utf8(transcript).unwrap(),
+            "?\nlook\nlook\n?\n!nope\nerror: unknown command: !nope (try !help)\n!quit\n"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use super::{RunState, VmError, VM};
+use std::fmt;
+
+/// Why a chain of amplifiers couldn't produce a thruster signal.
+#[derive(Debug, PartialEq, Clone)]
+pub enum AmplifierError {
+    /// An amplifier halted before the chain produced any output.
+    NoOutput,
+    /// [`max_thruster_signal`] was given no phase settings to try.
+    NoPhases,
+    Vm(VmError),
+}
+
+impl fmt::Display for AmplifierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoOutput => write!(f, "amplifier halted without output"),
+            Self::NoPhases => write!(f, "no phase settings given"),
+            Self::Vm(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl std::error::Error for AmplifierError {}
+
+impl From<VmError> for AmplifierError {
+    fn from(error: VmError) -> Self {
+        Self::Vm(error)
+    }
+}
+
+/// A chain of VMs running the same program, with the output of each one
+/// wired into the input of the next.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Amplifiers {
+    vms: Vec<VM>,
+}
+
+impl Amplifiers {
+    /// Creates one VM per phase setting, each seeded with its phase as the
+    /// first input.
+    pub fn new(src: &[i64], phases: &[i64]) -> Self {
+        let vms = phases
+            .iter()
+            .map(|phase| {
+                let mut vm = VM::new(src);
+                vm.push_input(*phase);
+                vm
+            })
+            .collect();
+
+        Self { vms }
+    }
+
+    /// Sends `signal` through the chain once and returns what comes out of the
+    /// last amplifier.
+    pub fn run(&mut self, signal: i64) -> Result<i64, AmplifierError> {
+        self.pass(signal)?.ok_or(AmplifierError::NoOutput)
+    }
+
+    /// Keeps feeding the output of the last amplifier back into the first one
+    /// until the chain halts, and returns the last signal it produced.
+    pub fn run_feedback(&mut self, signal: i64) -> Result<i64, AmplifierError> {
+        let mut signal = signal;
+        let mut last = None;
+
+        while let Some(x) = self.pass(signal)? {
+            signal = x;
+            last = Some(x);
+        }
+
+        last.ok_or(AmplifierError::NoOutput)
+    }
+
+    /// Runs every amplifier until its next output. Returns `None` as soon as
+    /// one of them halts instead.
+    fn pass(&mut self, signal: i64) -> Result<Option<i64>, VmError> {
+        let mut signal = signal;
+
+        for vm in self.vms.iter_mut() {
+            vm.push_input(signal);
+            match vm.resume()? {
+                RunState::Output(x) => signal = x,
+                RunState::Halted => return Ok(None),
+                RunState::NeedsInput => {
+                    return Err(VmError::InputExhausted {
+                        ip: vm.ip(),
+                        instruction: vm.word_at(vm.ip()),
+                    })
+                }
+            }
+        }
+
+        Ok(Some(signal))
+    }
+}
+
+/// Tries every ordering of `phases` and returns the highest thruster signal
+/// along with the phase settings that produced it.
+pub fn max_thruster_signal(
+    src: &[i64],
+    phases: &[i64],
+    feedback: bool,
+) -> Result<(i64, Vec<i64>), AmplifierError> {
+    if phases.is_empty() {
+        return Err(AmplifierError::NoPhases);
+    }
+
+    let mut best: Option<(i64, Vec<i64>)> = None;
+
+    for permutation in permutations(phases) {
+        let mut amplifiers = Amplifiers::new(src, &permutation);
+        let signal = if feedback {
+            amplifiers.run_feedback(0)?
+        } else {
+            amplifiers.run(0)?
+        };
+
+        if best.as_ref().is_none_or(|(max, _)| signal > *max) {
+            best = Some((signal, permutation));
+        }
+    }
+
+    best.ok_or(AmplifierError::NoPhases)
+}
+
+fn permutations(items: &[i64]) -> Vec<Vec<i64>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+
+    let mut res = vec![];
+    for (i, head) in items.iter().enumerate() {
+        let rest = [&items[..i], &items[i + 1..]].concat();
+        for mut tail in permutations(&rest) {
+            tail.insert(0, *head);
+            res.push(tail);
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permutations() {
+        assert_eq!(permutations(&[]), vec![vec![]]);
+        assert_eq!(
+            permutations(&[1, 2, 3]),
+            vec![
+                vec![1, 2, 3],
+                vec![1, 3, 2],
+                vec![2, 1, 3],
+                vec![2, 3, 1],
+                vec![3, 1, 2],
+                vec![3, 2, 1],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_amplifiers_in_series() {
+        let src = [
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        let mut amplifiers = Amplifiers::new(&src, &[4, 3, 2, 1, 0]);
+        assert_eq!(amplifiers.run(0), Ok(43210));
+
+        assert_eq!(
+            max_thruster_signal(&src, &[0, 1, 2, 3, 4], false),
+            Ok((43210, vec![4, 3, 2, 1, 0]))
+        );
+    }
+
+    #[test]
+    fn test_amplifiers_with_feedback() {
+        let src = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let mut amplifiers = Amplifiers::new(&src, &[9, 8, 7, 6, 5]);
+        assert_eq!(amplifiers.run_feedback(0), Ok(139629729));
+
+        assert_eq!(
+            max_thruster_signal(&src, &[5, 6, 7, 8, 9], true),
+            Ok((139629729, vec![9, 8, 7, 6, 5]))
+        );
+    }
+
+    #[test]
+    fn test_amplifiers_without_output() {
+        let mut amplifiers = Amplifiers::new(&[3, 0, 99], &[0, 1]);
+        assert_eq!(amplifiers.run(0), Err(AmplifierError::NoOutput));
+        let mut amplifiers = Amplifiers::new(&[3, 0, 99], &[0, 1]);
+        assert_eq!(amplifiers.run_feedback(0), Err(AmplifierError::NoOutput));
+
+        assert_eq!(
+            max_thruster_signal(&[3, 0, 99], &[0, 1], false),
+            Err(AmplifierError::NoOutput)
+        );
+        assert_eq!(
+            max_thruster_signal(&[3, 0, 3, 0, 4, 0, 99], &[], false),
+            Err(AmplifierError::NoPhases)
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use super::io::encode_line;
+use super::{RunState, VmError, VM};
+use std::io;
+
+/// What a program printed while running, as returned by [`Ascii::run`].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Printout {
+    /// The ASCII output, rendered as text.
+    pub text: String,
+    /// Output values that aren't ASCII codes, such as a puzzle's answer.
+    pub answers: Vec<i64>,
+    /// Whether the program halted, rather than stopping to wait for input.
+    pub halted: bool,
+}
+
+/// Talks to a program that speaks ASCII: lines sent to it are encoded as
+/// character codes followed by a newline, and its output comes back as text.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Ascii {
+    vm: VM,
+}
+
+impl Ascii {
+    pub fn new(vm: VM) -> Self {
+        Self { vm }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+
+    pub fn into_vm(self) -> VM {
+        self.vm
+    }
+
+    /// Queues `line` as input. Fails if it has characters outside ASCII.
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        for code in encode_line(line)? {
+            self.vm.push_input(code);
+        }
+        Ok(())
+    }
+
+    /// Runs until the program halts or needs more input than was sent.
+    pub fn run(&mut self) -> Result<Printout, VmError> {
+        let mut printout = Printout::default();
+
+        loop {
+            match self.vm.resume()? {
+                RunState::Output(x) => match u8::try_from(x) {
+                    Ok(byte) if byte.is_ascii() => printout.text.push(char::from(byte)),
+                    _ => printout.answers.push(x),
+                },
+                RunState::NeedsInput => return Ok(printout),
+                RunState::Halted => {
+                    printout.halted = true;
+                    return Ok(printout);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii() {
+        // prints a prompt, echoes a line, then prints 1000
+        let mut ascii = Ascii::new(VM::new(&[
+            104, 63, 104, 10, 3, 100, 4, 100, 1008, 100, 10, 101, 1006, 101, 4, 104, 1000, 99,
+        ]));
+
+        assert_eq!(
+            ascii.run(),
+            Ok(Printout {
+                text: "?\n".to_string(),
+                answers: vec![],
+                halted: false
+            })
+        );
+
+        ascii.send("look").unwrap();
+        assert_eq!(
+            ascii.run(),
+            Ok(Printout {
+                text: "look\n".to_string(),
+                answers: vec![1000],
+                halted: true
+            })
+        );
+        assert!(ascii.send("¿qué?").is_err());
+    }
+}
@@ -0,0 +1,569 @@
+//! Assembler for a small Intcode assembly language.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! .macro inc cell
+//!     add \cell, #1, \cell
+//! .endm
+//!
+//! start:  in [counter]
+//! loop:   inc [counter]
+//!         lt [counter], #10, [flag]
+//!         jnz [flag], #loop
+//!         out [counter]
+//!         hlt
+//! counter: .word 0
+//! flag:    .data 0
+//! ```
+//!
+//! Operands carry their mode as a sigil: `[addr]` for position, `#value` for
+//! immediate and `rb+offset` for relative mode. Values are numbers, labels or
+//! `label+offset`. Lines starting with a number and a colon, such as
+//! `0012:`, assert the current address, which lets the output of
+//! [`disassemble`](super::disassemble) be assembled back.
+//!
+//! Macros are defined between `.macro name param, ...` and `.endm`. Inside
+//! the body `\param` is replaced by the argument and `\@` by a counter that is
+//! unique per expansion, to build local labels.
+
+use super::{Opcode, ParamMode};
+use std::collections::HashMap;
+use std::fmt;
+
+const MAX_MACRO_DEPTH: usize = 32;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `src` into a program, ready to be run or written out as
+/// comma-separated Intcode.
+pub fn assemble(src: &str) -> Result<Vec<i64>, AsmError> {
+    let mut assembler = Assembler::default();
+    for (i, line) in src.lines().enumerate() {
+        assembler.process_line(line, i + 1, 0)?;
+    }
+
+    assembler.finish()
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Expr {
+    Number(i64),
+    Label { name: String, offset: i64 },
+}
+
+/// A word whose value may depend on labels defined later on.
+#[derive(Debug, PartialEq, Clone)]
+struct Word {
+    expr: Expr,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Assembler {
+    words: Vec<Word>,
+    labels: HashMap<String, usize>,
+    macros: HashMap<String, Macro>,
+    defining: Option<(String, Macro, usize)>,
+    expansions: usize,
+}
+
+impl Assembler {
+    fn process_line(&mut self, line: &str, number: usize, depth: usize) -> Result<(), AsmError> {
+        let error = |piece: &str, message: String| AsmError {
+            line: number,
+            column: column_of(line, piece),
+            message,
+        };
+
+        let code = line.split(';').next().unwrap_or("").trim();
+
+        if let Some((_, definition, _)) = self.defining.as_mut() {
+            if mnemonic_of(code) == ".endm" {
+                let (name, definition, _) = self.defining.take().unwrap();
+                self.macros.insert(name, definition);
+            } else {
+                definition.body.push(line.to_string());
+            }
+            return Ok(());
+        }
+
+        let mut rest = code;
+        while let Some((head, tail)) = split_label(rest) {
+            if head.chars().all(|c| c.is_ascii_digit()) {
+                let addr: usize = head
+                    .parse()
+                    .map_err(|_| error(head, format!("invalid address: {}", head)))?;
+                if addr != self.words.len() {
+                    return Err(error(
+                        head,
+                        format!("expected address {}, found {}", self.words.len(), addr),
+                    ));
+                }
+            } else {
+                self.define_label(head, number, line)?;
+            }
+            rest = tail.trim_start();
+        }
+
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let mnemonic = mnemonic_of(rest);
+        let operands = split_operands(rest[mnemonic.len()..].trim());
+
+        match mnemonic {
+            ".word" | ".data" => {
+                if operands.is_empty() {
+                    return Err(error(mnemonic, format!("{} needs a value", mnemonic)));
+                }
+                for operand in operands {
+                    let expr = parse_expr(operand).map_err(|x| error(operand, x))?;
+                    self.push(expr, number, column_of(line, operand));
+                }
+                Ok(())
+            }
+            ".macro" => {
+                let (name, params) = match operands.split_first() {
+                    Some((first, others)) => {
+                        let mut words = first.split_whitespace();
+                        let name = words.next().unwrap_or("");
+                        let mut params: Vec<&str> = words.collect();
+                        params.extend(others);
+                        (name, params)
+                    }
+                    None => return Err(error(mnemonic, "macro needs a name".to_string())),
+                };
+                if !is_identifier(name) {
+                    return Err(error(name, format!("invalid macro name: {}", name)));
+                }
+                let definition = Macro {
+                    params: params.iter().map(|x| x.to_string()).collect(),
+                    body: vec![],
+                };
+                self.defining = Some((name.to_string(), definition, number));
+                Ok(())
+            }
+            ".endm" => Err(error(mnemonic, ".endm without .macro".to_string())),
+            _ if mnemonic.starts_with('.') => {
+                Err(error(mnemonic, format!("unknown directive: {}", mnemonic)))
+            }
+            _ if self.macros.contains_key(mnemonic) => {
+                if depth >= MAX_MACRO_DEPTH {
+                    return Err(error(
+                        mnemonic,
+                        format!("macro expansion too deep: {}", mnemonic),
+                    ));
+                }
+                let definition = self.macros[mnemonic].clone();
+                if operands.len() != definition.params.len() {
+                    return Err(error(
+                        mnemonic,
+                        format!(
+                            "macro {} expects {} arguments, found {}",
+                            mnemonic,
+                            definition.params.len(),
+                            operands.len()
+                        ),
+                    ));
+                }
+
+                self.expansions += 1;
+                let unique = self.expansions.to_string();
+                for body_line in definition.body {
+                    let mut expanded = body_line.replace("\\@", &unique);
+                    for (param, arg) in definition.params.iter().zip(&operands) {
+                        expanded = substitute(&expanded, param, arg);
+                    }
+                    self.process_line(&expanded, number, depth + 1)
+                        .map_err(|x| AsmError {
+                            message: format!("{} (in macro {})", x.message, mnemonic),
+                            ..error(mnemonic, String::new())
+                        })?;
+                }
+                Ok(())
+            }
+            _ => {
+                let opcode = Opcode::ALL
+                    .iter()
+                    .find(|x| x.mnemonic() == mnemonic)
+                    .ok_or_else(|| error(mnemonic, format!("unknown mnemonic: {}", mnemonic)))?;
+                if operands.len() != opcode.arity() {
+                    return Err(error(
+                        mnemonic,
+                        format!(
+                            "{} expects {} operands, found {}",
+                            mnemonic,
+                            opcode.arity(),
+                            operands.len()
+                        ),
+                    ));
+                }
+
+                let mut word = opcode.code();
+                let mut params = vec![];
+                for (i, operand) in operands.iter().enumerate() {
+                    let (mode, expr) = parse_operand(operand).map_err(|x| error(operand, x))?;
+                    if mode == ParamMode::Immediate && opcode.modes_mask()[i].is_some() {
+                        return Err(error(
+                            operand,
+                            "write operand can't be immediate".to_string(),
+                        ));
+                    }
+                    word += mode_digit(mode) * 10_i64.pow(2 + i as u32);
+                    params.push((expr, column_of(line, operand)));
+                }
+
+                self.push(Expr::Number(word), number, column_of(line, mnemonic));
+                for (expr, column) in params {
+                    self.push(expr, number, column);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn define_label(&mut self, name: &str, number: usize, line: &str) -> Result<(), AsmError> {
+        let error = |message| AsmError {
+            line: number,
+            column: column_of(line, name),
+            message,
+        };
+
+        if !is_identifier(name) || name == "rb" {
+            return Err(error(format!("invalid label: {}", name)));
+        }
+        if self.labels.contains_key(name) {
+            return Err(error(format!("duplicate label: {}", name)));
+        }
+
+        self.labels.insert(name.to_string(), self.words.len());
+        Ok(())
+    }
+
+    fn push(&mut self, expr: Expr, line: usize, column: usize) {
+        self.words.push(Word { expr, line, column });
+    }
+
+    fn finish(self) -> Result<Vec<i64>, AsmError> {
+        if let Some((name, _, line)) = self.defining {
+            return Err(AsmError {
+                line,
+                column: 1,
+                message: format!("macro {} is missing .endm", name),
+            });
+        }
+
+        self.words
+            .iter()
+            .map(|word| {
+                let error = |message: String| AsmError {
+                    line: word.line,
+                    column: word.column,
+                    message,
+                };
+                match &word.expr {
+                    Expr::Number(x) => Ok(*x),
+                    Expr::Label { name, offset } => {
+                        let addr = self
+                            .labels
+                            .get(name)
+                            .ok_or_else(|| error(format!("undefined label: {}", name)))?;
+                        (*addr as i64)
+                            .checked_add(*offset)
+                            .ok_or_else(|| error("offset out of range".to_string()))
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// 1-based column of `piece`, which must be a subslice of `line`.
+fn column_of(line: &str, piece: &str) -> usize {
+    let offset = (piece.as_ptr() as usize).saturating_sub(line.as_ptr() as usize);
+    offset.min(line.len()) + 1
+}
+
+fn mnemonic_of(code: &str) -> &str {
+    code.split_whitespace().next().unwrap_or("")
+}
+
+/// Splits a leading `label:` off `code`, if there's one.
+fn split_label(code: &str) -> Option<(&str, &str)> {
+    let end = code.find(|c: char| c.is_whitespace() || c == ':')?;
+    if code[end..].starts_with(':') && end > 0 {
+        Some((&code[..end], &code[end + 1..]))
+    } else {
+        None
+    }
+}
+
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        return vec![];
+    }
+    text.split(',').map(|x| x.trim()).collect()
+}
+
+/// Replaces `\param` in a macro body line with `arg`, where it names the
+/// whole parameter: `\a` doesn't touch `\ab`.
+fn substitute(line: &str, param: &str, arg: &str) -> String {
+    let pattern = format!("\\{}", param);
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(i) = rest.find(&pattern) {
+        let after = &rest[i + pattern.len()..];
+        let whole = !after.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        result.push_str(&rest[..i]);
+        result.push_str(if whole { arg } else { &pattern });
+        rest = after;
+    }
+    result.push_str(rest);
+    result
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn mode_digit(mode: ParamMode) -> i64 {
+    match mode {
+        ParamMode::Position => 0,
+        ParamMode::Immediate => 1,
+        ParamMode::Relative => 2,
+    }
+}
+
+fn parse_operand(text: &str) -> Result<(ParamMode, Expr), String> {
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok((ParamMode::Immediate, parse_expr(value)?));
+    }
+    if let Some(value) = text.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        return Ok((ParamMode::Position, parse_expr(value)?));
+    }
+    if let Some(offset) = text.strip_prefix("rb") {
+        let offset = offset.trim();
+        if offset.is_empty() {
+            return Ok((ParamMode::Relative, Expr::Number(0)));
+        }
+        if let Some(value) = offset.strip_prefix('+') {
+            return Ok((ParamMode::Relative, parse_expr(value)?));
+        }
+        if offset.starts_with('-') {
+            return match parse_expr(offset)? {
+                Expr::Number(x) => Ok((ParamMode::Relative, Expr::Number(x))),
+                _ => Err(format!("invalid relative offset: {}", offset)),
+            };
+        }
+    }
+
+    Err(format!(
+        "missing mode in operand {} (use [addr], #value or rb+offset)",
+        text
+    ))
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let text = text.trim();
+    if let Ok(x) = text.parse() {
+        return Ok(Expr::Number(x));
+    }
+
+    let (name, offset) = match text.rfind(['+', '-']) {
+        Some(i) if i > 0 => {
+            let offset: i64 = text[i..]
+                .replace('+', "")
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid offset: {}", &text[i..]))?;
+            (text[..i].trim(), offset)
+        }
+        _ => (text, 0),
+    };
+
+    if is_identifier(name) && name != "rb" {
+        Ok(Expr::Label {
+            name: name.to_string(),
+            offset,
+        })
+    } else {
+        Err(format!("invalid value: {}", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{disassemble, parse_program, IterInput, VM};
+    use super::*;
+
+    fn run(src: &[i64], input: Vec<i64>) -> Vec<i64> {
+        let mut output = vec![];
+        VM::new(src)
+            .run(&mut IterInput(input.into_iter()), &mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_assemble_instructions() {
+        assert_eq!(
+            assemble("mul [4], #3, [4]\nout rb-2\nin rb+7\nhlt"),
+            Ok(vec![1002, 4, 3, 4, 204, -2, 203, 7, 99])
+        );
+    }
+
+    #[test]
+    fn test_assemble_labels_and_data() {
+        let src = "
+            ; count from the input up to 10
+            start:  in [counter]
+            loop:   add [counter], #1, [counter]
+                    out [counter]
+                    lt [counter], #10, [flag]
+                    jnz [flag], #loop
+                    hlt
+            counter: .word 0
+            flag:    .data 0, counter+1
+        ";
+        let program = assemble(src).unwrap();
+        assert_eq!(program[..5], [3, 16, 1001, 16, 1]);
+        assert_eq!(program[15..], [99, 0, 0, 17]);
+        assert_eq!(run(&program, vec![7]), vec![8, 9, 10]);
+
+        let text: Vec<String> = program.iter().map(|x| x.to_string()).collect();
+        assert_eq!(parse_program(&text.join(",")), Ok(program));
+    }
+
+    #[test]
+    fn test_assemble_macros() {
+        let src = "
+            .macro countdown cell
+            loop\\@: out \\cell
+                    add \\cell, #-1, \\cell
+                    jnz \\cell, #loop\\@
+            .endm
+            .macro twice first, second
+                    countdown \\first
+                    countdown \\second
+            .endm
+
+                    twice [a], [b]
+                    hlt
+            a:      .word 2
+            b:      .word 1
+        ";
+        let program = assemble(src).unwrap();
+        assert_eq!(run(&program, vec![]), vec![2, 1, 1]);
+    }
+
+    #[test]
+    fn test_macro_params_match_whole_names() {
+        let src = "
+            .macro copy a, ab
+                    add \\a, #0, \\ab
+            .endm
+                    copy [x], [y]
+                    out [y]
+                    hlt
+            x:      .word 5
+            y:      .word 0
+        ";
+        let program = assemble(src).unwrap();
+        assert_eq!(program[..4], [1001, 7, 0, 8]);
+        assert_eq!(run(&program, vec![]), vec![5]);
+        assert_eq!(
+            substitute("\\a \\ab \\a_1 \\a,", "a", "#1"),
+            "#1 \\ab \\a_1 #1,"
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let error = |line, column, message: &str| {
+            Err(AsmError {
+                line,
+                column,
+                message: message.to_string(),
+            })
+        };
+
+        assert_eq!(
+            assemble("hlt\n  foo [1]"),
+            error(2, 3, "unknown mnemonic: foo")
+        );
+        assert_eq!(
+            assemble("add [1], #2"),
+            error(1, 1, "add expects 3 operands, found 2")
+        );
+        assert_eq!(
+            assemble("add [1], #2, #3"),
+            error(1, 14, "write operand can't be immediate")
+        );
+        assert_eq!(
+            assemble("out 12"),
+            error(
+                1,
+                5,
+                "missing mode in operand 12 (use [addr], #value or rb+offset)"
+            )
+        );
+        assert_eq!(
+            assemble("jnz #1, #nowhere"),
+            error(1, 9, "undefined label: nowhere")
+        );
+        assert_eq!(
+            assemble("hlt\na: .word a+9223372036854775807"),
+            error(2, 10, "offset out of range")
+        );
+        assert_eq!(
+            assemble("a: hlt\na: hlt"),
+            error(2, 1, "duplicate label: a")
+        );
+        assert_eq!(
+            assemble("hlt\n0003: hlt"),
+            error(2, 1, "expected address 1, found 3")
+        );
+        assert_eq!(
+            assemble(".macro m\nhlt"),
+            error(1, 1, "macro m is missing .endm")
+        );
+    }
+
+    #[test]
+    fn test_round_trip_with_disassembler() {
+        let programs: [&[i64]; 4] = [
+            &[1002, 4, 3, 4, 33],
+            &[11101, 1, 1, 5, 99],
+            &[1105, 1, 5, 7, 7, 204, -3, 99],
+            &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9],
+        ];
+
+        for program in programs {
+            let listing = disassemble(program).to_string();
+            assert_eq!(assemble(&listing), Ok(program.to_vec()), "{}", listing);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use super::disasm::{format_instruction, reachable_instructions, successors};
+use super::{Instruction, Opcode, ParamMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// A run of instructions that always execute one after the other: only the
+/// first one is jumped to, and only the last one jumps or halts.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+    /// Whether the block ends in a jump through position or relative mode,
+    /// whose target is only known at run time.
+    pub dynamic_exit: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EdgeKind {
+    /// Execution carries on to the next instruction.
+    FallThrough,
+    /// A jump in immediate mode is taken.
+    Jump,
+}
+
+/// An edge between the blocks starting at `from` and `to`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// Control-flow graph of a program, as returned by [`control_flow_graph`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+    /// Addresses that execution reaches but that don't hold a valid
+    /// instruction, often because the program writes one there first.
+    pub undecoded: BTreeSet<usize>,
+    /// Cells that are executed, or about to be, and also written to by an
+    /// instruction through position mode.
+    pub self_modifying: BTreeSet<usize>,
+    program: Vec<i64>,
+}
+
+/// Splits the code reachable from address 0, as found by the disassembler,
+/// into basic blocks. Code that is only reached through dynamic jumps isn't
+/// found.
+pub fn control_flow_graph(program: &[i64]) -> ControlFlowGraph {
+    let code = reachable_instructions(program);
+
+    let mut leaders = BTreeSet::from([0]);
+    let mut undecoded = BTreeSet::new();
+    for (addr, instruction) in &code {
+        let successors = successors(program, *addr, *instruction);
+        if is_jump(instruction.opcode) {
+            leaders.extend(successors.next);
+        }
+        leaders.extend(successors.target);
+        undecoded.extend(successors.next);
+    }
+    undecoded.extend(&leaders);
+    undecoded.retain(|x| *x < program.len() && !code.contains_key(x));
+    leaders.retain(|x| code.contains_key(x));
+
+    let mut blocks = vec![];
+    let mut edges = vec![];
+    for start in &leaders {
+        let mut block = BasicBlock {
+            start: *start,
+            instructions: vec![],
+            dynamic_exit: false,
+        };
+        let mut addr = *start;
+
+        loop {
+            let instruction = code[&addr];
+            block.instructions.push((addr, instruction));
+            let successors = successors(program, addr, instruction);
+            let mut edge = |to: usize, kind: EdgeKind| {
+                if code.contains_key(&to) || undecoded.contains(&to) {
+                    edges.push(Edge {
+                        from: *start,
+                        to,
+                        kind,
+                    });
+                }
+            };
+
+            if is_jump(instruction.opcode) || instruction.opcode == Opcode::Halt {
+                if let Some(next) = successors.next {
+                    edge(next, EdgeKind::FallThrough);
+                }
+                if let Some(target) = successors.target {
+                    edge(target, EdgeKind::Jump);
+                }
+                block.dynamic_exit = successors.dynamic;
+                break;
+            }
+
+            match successors.next {
+                Some(next) if code.contains_key(&next) && !leaders.contains(&next) => addr = next,
+                Some(next) => {
+                    edge(next, EdgeKind::FallThrough);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        blocks.push(block);
+    }
+
+    ControlFlowGraph {
+        blocks,
+        edges,
+        self_modifying: self_modifying_cells(program, &code, &undecoded),
+        undecoded,
+        program: program.to_vec(),
+    }
+}
+
+fn is_jump(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::JumpNotZero | Opcode::JumpZero)
+}
+
+/// Cells written through position mode (or immediate mode, which the VM takes
+/// as position) that are also part of an instruction, or are reached without
+/// holding one. Writes through relative
+/// mode can't be resolved statically.
+fn self_modifying_cells(
+    program: &[i64],
+    code: &BTreeMap<usize, Instruction>,
+    undecoded: &BTreeSet<usize>,
+) -> BTreeSet<usize> {
+    let executed: BTreeSet<usize> = code
+        .iter()
+        .flat_map(|(addr, instruction)| *addr..=addr + instruction.opcode.arity())
+        .chain(undecoded.iter().copied())
+        .collect();
+
+    code.iter()
+        .flat_map(|(addr, instruction)| {
+            (0..3).filter_map(move |i| {
+                let is_write = instruction.opcode.modes_mask()[i].is_some();
+                match instruction.modes[i] {
+                    ParamMode::Relative => None,
+                    _ if is_write => usize::try_from(program[addr + 1 + i]).ok(),
+                    _ => None,
+                }
+            })
+        })
+        .filter(|x| executed.contains(x))
+        .collect()
+}
+
+impl ControlFlowGraph {
+    /// Writes the graph in Graphviz's DOT language. Blocks with instructions
+    /// that get overwritten are drawn in red, addresses without a valid
+    /// instruction get a `.data` node, and dynamic jumps are dashed edges to a
+    /// `?` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph intcode {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in &self.blocks {
+            let mut label = String::new();
+            let mut modified = false;
+            for (addr, instruction) in &block.instructions {
+                let cells = *addr..=addr + instruction.opcode.arity();
+                let words = &self.program[cells.clone()];
+                write!(
+                    label,
+                    "{:04}: {}",
+                    addr,
+                    format_instruction(*instruction, words)
+                )
+                .unwrap();
+                if cells.into_iter().any(|x| self.self_modifying.contains(&x)) {
+                    label.push_str(" ; overwritten");
+                    modified = true;
+                }
+                label.push_str("\\l");
+            }
+
+            let color = if modified { ", color=red" } else { "" };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, color).unwrap();
+        }
+
+        for addr in &self.undecoded {
+            let (note, color) = match self.self_modifying.contains(addr) {
+                true => (" ; overwritten", ", color=red"),
+                false => ("", ""),
+            };
+            writeln!(
+                dot,
+                "    b{} [label=\"{:04}: .data {}{}\\l\"{}];",
+                addr, addr, self.program[*addr], note, color
+            )
+            .unwrap();
+        }
+
+        if self.blocks.iter().any(|x| x.dynamic_exit) {
+            dot.push_str("    dynamic [shape=diamond, label=\"?\"];\n");
+        }
+
+        for block in &self.blocks {
+            for edge in self.edges.iter().filter(|x| x.from == block.start) {
+                let attributes = match edge.kind {
+                    EdgeKind::FallThrough => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                };
+                writeln!(dot, "    b{} -> b{}{};", edge.from, edge.to, attributes).unwrap();
+            }
+            if block.dynamic_exit {
+                writeln!(dot, "    b{} -> dynamic [style=dashed];", block.start).unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // in [20], jnz [20], #7, out #0, add #2, #3, [12], out [0], jz [20], [21],
+    // hlt, followed by data
+    const PROGRAM: [i64; 22] = [
+        3, 20, 1005, 20, 7, 104, 0, 1101, 2, 3, 12, 4, 0, 6, 20, 21, 99, 0, 0, 0, 0, 0,
+    ];
+
+    #[test]
+    fn test_control_flow_graph() {
+        let cfg = control_flow_graph(&PROGRAM);
+
+        let starts: Vec<usize> = cfg.blocks.iter().map(|x| x.start).collect();
+        assert_eq!(starts, vec![0, 5, 7, 16]);
+        assert_eq!(cfg.blocks[2].instructions.len(), 3);
+        assert!(cfg.blocks[2].dynamic_exit);
+
+        let edges: Vec<(usize, usize, EdgeKind)> =
+            cfg.edges.iter().map(|x| (x.from, x.to, x.kind)).collect();
+        assert_eq!(
+            edges,
+            vec![
+                (0, 5, EdgeKind::FallThrough),
+                (0, 7, EdgeKind::Jump),
+                (5, 7, EdgeKind::FallThrough),
+                (7, 16, EdgeKind::FallThrough),
+            ]
+        );
+        assert_eq!(cfg.self_modifying, BTreeSet::from([12]));
+    }
+
+    #[test]
+    fn test_code_written_before_running() {
+        // 98 isn't an opcode, but the add turns it into a mul before it runs
+        let cfg = control_flow_graph(&[1101, 1, 1, 4, 98, 0, 0, 0]);
+        assert_eq!(cfg.undecoded, BTreeSet::from([4]));
+        assert_eq!(cfg.self_modifying, BTreeSet::from([4]));
+        assert_eq!(
+            cfg.edges,
+            vec![Edge {
+                from: 0,
+                to: 4,
+                kind: EdgeKind::FallThrough
+            }]
+        );
+        assert!(cfg
+            .to_dot()
+            .contains("    b4 [label=\"0004: .data 98 ; overwritten\\l\", color=red];\n"));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        assert_eq!(
+            control_flow_graph(&PROGRAM).to_dot(),
+            [
+                "digraph intcode {\n",
+                "    node [shape=box, fontname=\"monospace\"];\n",
+                "    b0 [label=\"0000: in [20]\\l0002: jnz [20], #7\\l\"];\n",
+                "    b5 [label=\"0005: out #0\\l\"];\n",
+                "    b7 [label=\"0007: add #2, #3, [12]\\l0011: out [0] ; overwritten\\l",
+                "0013: jz [20], [21]\\l\", color=red];\n",
+                "    b16 [label=\"0016: hlt\\l\"];\n",
+                "    dynamic [shape=diamond, label=\"?\"];\n",
+                "    b0 -> b5;\n",
+                "    b0 -> b7 [label=\"jump\"];\n",
+                "    b5 -> b7;\n",
+                "    b7 -> b16;\n",
+                "    b7 -> dynamic [style=dashed];\n",
+                "}\n",
+            ]
+            .concat()
+        );
+    }
+}
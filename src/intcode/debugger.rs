@@ -0,0 +1,507 @@
+//! Step debugger for the Intcode [`VM`], driven by text commands.
+//!
+//! Type `help` at the prompt for the list of commands.
+
+use super::{Instruction, ListingLine, Opcode, RunState, VmError, VM};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+const HELP: &str = "\
+commands:
+  s, step [n]           execute n instructions (default 1)
+  c, continue           run until a breakpoint, watchpoint, input or halt
+  bs, back [n]          undo n instructions (default 1)
+  rw, rewind <addr>     undo instructions until the one at addr is next
+  b, break <addr|op>    break at an address or on an opcode mnemonic
+  d, delete <addr|op>   remove a breakpoint
+  w, watch <addr>       stop when the cell at addr changes
+  unwatch <addr>        remove a watchpoint
+  r, regs               show ip, relative base and pending input
+  i, inst               show the current instruction
+  x, mem <addr> [n]     dump n cells starting at addr (default 8)
+  set <addr> <value>    write value to memory
+  in, input <v> ...     queue input values
+  q, quit               leave the debugger";
+
+/// Why execution stopped under the debugger.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Stop {
+    Breakpoint(usize),
+    OpcodeBreakpoint(Opcode),
+    Watchpoint { addr: usize, old: i64, new: i64 },
+    NeedsInput,
+    Halted,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Debugger {
+    vm: VM,
+    breakpoints: BTreeSet<usize>,
+    opcode_breakpoints: Vec<Opcode>,
+    watchpoints: BTreeMap<usize, i64>,
+}
+
+impl Debugger {
+    pub fn new(vm: VM) -> Self {
+        Self {
+            vm,
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: vec![],
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    /// Reads commands from `input` until it's exhausted or the user quits.
+    pub fn repl(&mut self, input: impl io::BufRead, output: &mut impl io::Write) -> io::Result<()> {
+        writeln!(output, "{}", self.current_instruction())?;
+        write!(output, "(dbg) ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            match self.execute(&line, output) {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(message) => writeln!(output, "error: {}", message)?,
+            }
+            write!(output, "(dbg) ")?;
+            output.flush()?;
+        }
+
+        writeln!(output)
+    }
+
+    /// Runs a single command, writing its results to `output`. Returns whether
+    /// the command asked to quit.
+    pub fn execute(&mut self, command: &str, output: &mut impl io::Write) -> Result<bool, String> {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(false);
+        };
+        let args: Vec<&str> = words.collect();
+        let io_error = |x: io::Error| format!("{}", x);
+
+        match name {
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(x) => parse_count(x)?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if let Some(stop) = self.step(output).map_err(|x| x.to_string())? {
+                        writeln!(output, "{}", describe(&stop)).map_err(io_error)?;
+                        break;
+                    }
+                }
+                writeln!(output, "{}", self.current_instruction()).map_err(io_error)?;
+            }
+            "c" | "continue" => {
+                let stop = self.cont(output).map_err(|x| x.to_string())?;
+                writeln!(output, "{}", describe(&stop)).map_err(io_error)?;
+                writeln!(output, "{}", self.current_instruction()).map_err(io_error)?;
+            }
+            "bs" | "back" => {
+                let count = match args.first() {
+                    Some(x) => parse_count(x)?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if !self.vm.step_back() {
+                        writeln!(output, "start of history").map_err(io_error)?;
+                        break;
+                    }
+                }
+                self.sync_watchpoints();
+                writeln!(output, "{}", self.current_instruction()).map_err(io_error)?;
+            }
+            "rw" | "rewind" => {
+                let addr = parse_address(args.first())?;
+                if !self.vm.run_back_to(addr) {
+                    writeln!(output, "start of history").map_err(io_error)?;
+                }
+                self.sync_watchpoints();
+                writeln!(output, "{}", self.current_instruction()).map_err(io_error)?;
+            }
+            "b" | "break" => match parse_target(&args)? {
+                Target::Address(addr) => {
+                    self.breakpoints.insert(addr);
+                }
+                Target::Opcode(opcode) => {
+                    if !self.opcode_breakpoints.contains(&opcode) {
+                        self.opcode_breakpoints.push(opcode);
+                    }
+                }
+            },
+            "d" | "delete" => match parse_target(&args)? {
+                Target::Address(addr) => {
+                    self.breakpoints.remove(&addr);
+                }
+                Target::Opcode(opcode) => self.opcode_breakpoints.retain(|x| *x != opcode),
+            },
+            "w" | "watch" => {
+                let addr = parse_address(args.first())?;
+                let value = self.vm.read_mem(addr).map_err(|x| x.to_string())?;
+                self.watchpoints.insert(addr, value);
+            }
+            "unwatch" => {
+                let addr = parse_address(args.first())?;
+                self.watchpoints.remove(&addr);
+            }
+            "r" | "regs" => {
+                let input: Vec<String> = self
+                    .vm
+                    .pending_input()
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect();
+                writeln!(
+                    output,
+                    "ip={} rb={} input=[{}]",
+                    self.vm.ip(),
+                    self.vm.relative_base(),
+                    input.join(", ")
+                )
+                .map_err(io_error)?;
+            }
+            "i" | "inst" => {
+                writeln!(output, "{}", self.current_instruction()).map_err(io_error)?;
+            }
+            "x" | "mem" => {
+                let addr = parse_address(args.first())?;
+                let count = match args.get(1) {
+                    Some(x) => parse_count(x)?,
+                    None => 8,
+                };
+                let end = addr
+                    .checked_add(count)
+                    .ok_or_else(|| format!("{} cells from {} is out of range", count, addr))?;
+                let cells = (addr..end)
+                    .map(|x| self.vm.read_mem(x).map(|value| value.to_string()))
+                    .collect::<Result<Vec<String>, VmError>>()
+                    .map_err(|x| x.to_string())?;
+                writeln!(output, "{:04}: {}", addr, cells.join(" ")).map_err(io_error)?;
+            }
+            "set" => {
+                let addr = parse_address(args.first())?;
+                let value = parse_number(args.get(1).ok_or("missing value")?)?;
+                self.vm.write_mem(addr, value).map_err(|x| x.to_string())?;
+                if let Some(watched) = self.watchpoints.get_mut(&addr) {
+                    *watched = value;
+                }
+            }
+            "in" | "input" => {
+                if args.is_empty() {
+                    return Err("missing input values".to_string());
+                }
+                for arg in args {
+                    self.vm.push_input(parse_number(arg)?);
+                }
+            }
+            "h" | "help" => writeln!(output, "{}", HELP).map_err(io_error)?,
+            "q" | "quit" => return Ok(true),
+            _ => return Err(format!("unknown command: {} (try help)", name)),
+        }
+
+        Ok(false)
+    }
+
+    /// Executes one instruction, printing any output it produces. Returns why
+    /// the VM can't go on, if that's the case.
+    pub fn step(&mut self, output: &mut impl io::Write) -> Result<Option<Stop>, VmError> {
+        let state = self.vm.step()?;
+        if let Some(RunState::Output(x)) = state {
+            writeln!(output, "output: {}", x).map_err(|x| self.vm.io_error(x))?;
+        }
+
+        if let Some(stop) = self.check_watchpoints() {
+            return Ok(Some(stop));
+        }
+
+        match state {
+            Some(RunState::NeedsInput) => Ok(Some(Stop::NeedsInput)),
+            Some(RunState::Halted) => Ok(Some(Stop::Halted)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Runs until a breakpoint or watchpoint triggers, or the VM needs input
+    /// or halts. A breakpoint at the current instruction doesn't trigger, so
+    /// that continuing from it makes progress.
+    pub fn cont(&mut self, output: &mut impl io::Write) -> Result<Stop, VmError> {
+        let mut first = true;
+        loop {
+            if !first {
+                if let Some(stop) = self.check_breakpoints() {
+                    return Ok(stop);
+                }
+            }
+            first = false;
+
+            if let Some(stop) = self.step(output)? {
+                return Ok(stop);
+            }
+        }
+    }
+
+    fn check_breakpoints(&self) -> Option<Stop> {
+        if self.breakpoints.contains(&self.vm.ip()) {
+            return Some(Stop::Breakpoint(self.vm.ip()));
+        }
+
+        let instruction = self
+            .vm
+            .read_mem(self.vm.ip())
+            .and_then(Instruction::try_from)
+            .ok()?;
+        self.opcode_breakpoints
+            .contains(&instruction.opcode)
+            .then_some(Stop::OpcodeBreakpoint(instruction.opcode))
+    }
+
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        for (addr, old) in self.watchpoints.iter_mut() {
+            let new = self.vm.read_mem(*addr).unwrap_or(*old);
+            if new != *old {
+                let stop = Stop::Watchpoint {
+                    addr: *addr,
+                    old: *old,
+                    new,
+                };
+                *old = new;
+                return Some(stop);
+            }
+        }
+
+        None
+    }
+
+    /// Takes the current values of watched cells as the ones to compare
+    /// against, after going back in time changed them.
+    fn sync_watchpoints(&mut self) {
+        for (addr, value) in self.watchpoints.iter_mut() {
+            *value = self.vm.read_mem(*addr).unwrap_or(*value);
+        }
+    }
+
+    fn current_instruction(&self) -> String {
+        let ip = self.vm.ip();
+        let Ok(word) = self.vm.read_mem(ip) else {
+            return format!("{:04}: <out of bounds>", ip);
+        };
+
+        let line = match Instruction::try_from(word) {
+            Ok(instruction) => {
+                let words: Result<Vec<i64>, VmError> = (ip..=ip + instruction.opcode.arity())
+                    .map(|x| self.vm.read_mem(x))
+                    .collect();
+                match words {
+                    Ok(words) => ListingLine::Instruction {
+                        addr: ip,
+                        words,
+                        instruction,
+                    },
+                    Err(_) => ListingLine::Data {
+                        addr: ip,
+                        words: vec![word],
+                    },
+                }
+            }
+            Err(_) => ListingLine::Data {
+                addr: ip,
+                words: vec![word],
+            },
+        };
+
+        format!("=> {}", line)
+    }
+}
+
+enum Target {
+    Address(usize),
+    Opcode(Opcode),
+}
+
+fn parse_target(args: &[&str]) -> Result<Target, String> {
+    let arg = args.first().ok_or("missing address or opcode")?;
+    if let Some(opcode) = Opcode::ALL.iter().find(|x| x.mnemonic() == *arg) {
+        return Ok(Target::Opcode(*opcode));
+    }
+
+    parse_address(Some(arg)).map(Target::Address)
+}
+
+fn parse_number(text: &str) -> Result<i64, String> {
+    text.parse()
+        .map_err(|_| format!("invalid number: {}", text))
+}
+
+fn parse_count(text: &str) -> Result<usize, String> {
+    text.parse().map_err(|_| format!("invalid count: {}", text))
+}
+
+fn parse_address(text: Option<&&str>) -> Result<usize, String> {
+    let text = text.ok_or("missing address")?;
+    text.parse()
+        .map_err(|_| format!("invalid address: {}", text))
+}
+
+fn describe(stop: &Stop) -> String {
+    match stop {
+        Stop::Breakpoint(addr) => format!("breakpoint at {}", addr),
+        Stop::OpcodeBreakpoint(opcode) => format!("breakpoint on {}", opcode.mnemonic()),
+        Stop::Watchpoint { addr, old, new } => format!("watch [{}]: {} -> {}", addr, old, new),
+        Stop::NeedsInput => "waiting for input".to_string(),
+        Stop::Halted => "halted".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // in [9], add [9], #2, [10], out [10], hlt, 0, 0
+    const PROGRAM: [i64; 11] = [3, 9, 1001, 9, 2, 10, 4, 10, 99, 0, 0];
+
+    fn session(commands: &str) -> String {
+        let mut debugger = Debugger::new(VM::new(&PROGRAM));
+        let mut output = vec![];
+        debugger.repl(commands.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_step_and_registers() {
+        let mut debugger = Debugger::new(VM::new(&PROGRAM));
+        let mut output = vec![];
+
+        assert_eq!(debugger.step(&mut output), Ok(Some(Stop::NeedsInput)));
+        debugger.execute("input 5", &mut output).unwrap();
+        assert_eq!(debugger.step(&mut output), Ok(None));
+        assert_eq!(debugger.vm().ip(), 2);
+
+        output.clear();
+        debugger.execute("regs", &mut output).unwrap();
+        debugger.execute("step 3", &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "ip=2 rb=0 input=[]\noutput: 7\nhalted\n=> 0008: hlt                             ; 99\n"
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = Debugger::new(VM::new(&PROGRAM));
+        let mut output = vec![];
+        debugger.execute("in 1", &mut output).unwrap();
+
+        debugger.execute("break 6", &mut output).unwrap();
+        assert_eq!(debugger.cont(&mut output), Ok(Stop::Breakpoint(6)));
+        assert_eq!(debugger.cont(&mut output), Ok(Stop::Halted));
+
+        let mut debugger = Debugger::new(VM::new(&PROGRAM));
+        debugger.execute("in 1", &mut output).unwrap();
+        debugger.execute("b add", &mut output).unwrap();
+        assert_eq!(
+            debugger.cont(&mut output),
+            Ok(Stop::OpcodeBreakpoint(Opcode::Add))
+        );
+        debugger.execute("d add", &mut output).unwrap();
+        assert_eq!(debugger.cont(&mut output), Ok(Stop::Halted));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = Debugger::new(VM::new(&PROGRAM));
+        let mut output = vec![];
+        debugger.execute("in 4", &mut output).unwrap();
+        debugger.execute("watch 10", &mut output).unwrap();
+
+        assert_eq!(
+            debugger.cont(&mut output),
+            Ok(Stop::Watchpoint {
+                addr: 10,
+                old: 0,
+                new: 6
+            })
+        );
+        assert_eq!(debugger.vm().ip(), 6);
+    }
+
+    #[test]
+    fn test_back_and_rewind() {
+        let mut debugger = Debugger::new(VM::new(&PROGRAM).with_history(100));
+        let mut output = vec![];
+        debugger.execute("in 4", &mut output).unwrap();
+        debugger.execute("watch 10", &mut output).unwrap();
+        debugger.execute("step 2", &mut output).unwrap();
+
+        output.clear();
+        debugger.execute("back", &mut output).unwrap();
+        debugger.execute("rewind 0", &mut output).unwrap();
+        debugger.execute("back 5", &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output.clone()).unwrap(),
+            [
+                "=> 0002: add [9], #2, [10]               ; 1001 9 2 10\n",
+                "=> 0000: in [9]                          ; 3 9\n",
+                "start of history\n",
+                "=> 0000: in [9]                          ; 3 9\n",
+            ]
+            .concat()
+        );
+
+        // the watched cell went back to 0, so redoing the add triggers it again
+        assert_eq!(
+            debugger.cont(&mut output),
+            Ok(Stop::Watchpoint {
+                addr: 10,
+                old: 0,
+                new: 6
+            })
+        );
+    }
+
+    #[test]
+    fn test_repl_session() {
+        let transcript = session("x 0 4\nset 4 40\ni\nin 2\nc\nfoo\nq\nstep\n");
+        assert_eq!(
+            transcript,
+            [
+                "=> 0000: in [9]                          ; 3 9\n",
+                "(dbg) 0000: 3 9 1001 9\n",
+                "(dbg) (dbg) => 0000: in [9]                          ; 3 9\n",
+                "(dbg) (dbg) output: 42\n",
+                "halted\n",
+                "=> 0008: hlt                             ; 99\n",
+                "(dbg) error: unknown command: foo (try help)\n",
+                "(dbg) ",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_invalid_counts() {
+        let mut debugger = Debugger::new(VM::new(&PROGRAM));
+        let mut output = vec![];
+
+        assert_eq!(
+            debugger.execute("step -1", &mut output),
+            Err("invalid count: -1".to_string())
+        );
+        assert_eq!(
+            debugger.execute("x 1 -1", &mut output),
+            Err("invalid count: -1".to_string())
+        );
+        assert_eq!(
+            debugger.execute(&format!("x {} 2", usize::MAX), &mut output),
+            Err(format!("2 cells from {} is out of range", usize::MAX))
+        );
+        assert_eq!(debugger.vm().ip(), 0);
+        assert!(output.is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use super::VmError;
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A peripheral mapped over a range of addresses, such as a screen buffer, a
+/// timer or a random source. Reads and writes to the range reach the device
+/// instead of memory, with `offset` counted from the start of the range.
+///
+/// Devices are attached behind an `Arc<Mutex<_>>` so that the caller can keep
+/// a handle to inspect or drive them while the program runs.
+pub trait Device: Send {
+    fn read(&mut self, offset: usize) -> io::Result<i64>;
+    fn write(&mut self, offset: usize, value: i64) -> io::Result<()>;
+}
+
+type SharedDevice = Arc<Mutex<dyn Device>>;
+
+/// The devices attached to a VM and the ranges they're mapped over.
+#[derive(Clone, Default)]
+pub(crate) struct Devices(Vec<(Range<usize>, SharedDevice)>, Cell<bool>);
+
+impl Devices {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn is_mapped(&self, addr: usize) -> bool {
+        self.find(addr).is_some()
+    }
+
+    pub(crate) fn overlaps(&self, range: &Range<usize>) -> bool {
+        self.0
+            .iter()
+            .any(|(mapped, _)| mapped.start < range.end && range.start < mapped.end)
+    }
+
+    pub(crate) fn push(&mut self, range: Range<usize>, device: SharedDevice) {
+        self.0.push((range, device));
+    }
+
+    /// Whether any device was read or written since the last call. Like input
+    /// and output, that's the program talking to the world outside the VM.
+    pub(crate) fn take_accessed(&self) -> bool {
+        self.1.replace(false)
+    }
+
+    /// Reads from the device mapped at `addr`, or `None` if there isn't one.
+    pub(crate) fn read(&self, addr: usize) -> Option<Result<i64, VmError>> {
+        let (offset, device) = self.find(addr)?;
+        let mut device = device.lock().unwrap_or_else(PoisonError::into_inner);
+
+        self.1.set(true);
+        Some(device.read(offset).map_err(device_error))
+    }
+
+    /// Writes to the device mapped at `addr`, or `None` if there isn't one.
+    pub(crate) fn write(&self, addr: usize, value: i64) -> Option<Result<(), VmError>> {
+        let (offset, device) = self.find(addr)?;
+        let mut device = device.lock().unwrap_or_else(PoisonError::into_inner);
+
+        self.1.set(true);
+        Some(device.write(offset, value).map_err(device_error))
+    }
+
+    fn find(&self, addr: usize) -> Option<(usize, &SharedDevice)> {
+        self.0
+            .iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(range, device)| (addr - range.start, device))
+    }
+}
+
+fn device_error(error: io::Error) -> VmError {
+    VmError::Io {
+        ip: 0,
+        instruction: 0,
+        message: error.to_string(),
+    }
+}
+
+impl fmt::Debug for Devices {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(range, _)| range))
+            .finish()
+    }
+}
+
+impl PartialEq for Devices {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|(a, b)| a.0 == b.0 && Arc::ptr_eq(&a.1, &b.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RunState, VM};
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A 4x2 screen of characters.
+    struct Screen([i64; 8]);
+
+    impl Device for Screen {
+        fn read(&mut self, offset: usize) -> io::Result<i64> {
+            Ok(self.0[offset])
+        }
+
+        fn write(&mut self, offset: usize, value: i64) -> io::Result<()> {
+            self.0[offset] = value;
+            Ok(())
+        }
+    }
+
+    /// Counts up on every read. Read-only.
+    struct Timer(i64);
+
+    impl Device for Timer {
+        fn read(&mut self, _: usize) -> io::Result<i64> {
+            self.0 += 1;
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _: usize, _: i64) -> io::Result<()> {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "timer is read-only",
+            ))
+        }
+    }
+
+    #[test]
+    fn test_devices() {
+        let screen = Arc::new(Mutex::new(Screen([0; 8])));
+        // copies the timer to the screen twice, then outputs the second cell
+        let mut vm = VM::new(&[1001, 200, 0, 100, 1001, 200, 0, 101, 4, 101, 99])
+            .with_device(100..108, screen.clone())
+            .with_device(200..201, Arc::new(Mutex::new(Timer(0))));
+
+        assert_eq!(vm.resume(), Ok(RunState::Output(2)));
+        assert_eq!(screen.lock().unwrap().0[..3], [1, 2, 0]);
+        assert_eq!(vm.read_mem(200), Ok(3));
+        assert_eq!(vm.read_mem(104), Ok(0));
+
+        vm.write_mem(107, 9).unwrap();
+        assert_eq!(screen.lock().unwrap().0[7], 9);
+    }
+
+    #[test]
+    fn test_device_errors() {
+        let mut vm =
+            VM::new(&[1101, 1, 1, 10, 99]).with_device(10..11, Arc::new(Mutex::new(Timer(0))));
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::Io {
+                ip: 0,
+                instruction: 1101,
+                message: "timer is read-only".to_string()
+            })
+        );
+    }
+
+    /// Always reads as the same word, and counts how often it was read.
+    struct Rom(i64, usize);
+
+    impl Device for Rom {
+        fn read(&mut self, _: usize) -> io::Result<i64> {
+            self.1 += 1;
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _: usize, _: i64) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_errors_dont_read_devices() {
+        let rom = Arc::new(Mutex::new(Rom(3, 0)));
+        let mut vm = VM::new(&[3, 0, 99]).with_device(0..1, rom.clone());
+        assert_eq!(
+            vm.run(&mut VecDeque::new(), &mut vec![]),
+            Err(VmError::InputExhausted {
+                ip: 0,
+                instruction: 3
+            })
+        );
+        assert_eq!(rom.lock().unwrap().1, 1);
+    }
+
+    /// Counts down to 0 on every read, then stays there.
+    struct Countdown(i64);
+
+    impl Device for Countdown {
+        fn read(&mut self, _: usize) -> io::Result<i64> {
+            self.0 = (self.0 - 1).max(0);
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _: usize, _: i64) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_polling_a_device_is_not_a_loop() {
+        // spins until the countdown reads as 0, then outputs 7
+        let mut vm = VM::new(&[1005, 100, 0, 104, 7, 99])
+            .with_device(100..101, Arc::new(Mutex::new(Countdown(10))))
+            .with_loop_detection();
+        assert_eq!(vm.resume(), Ok(RunState::Output(7)));
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps")]
+    fn test_devices_cannot_overlap() {
+        VM::new(&[99])
+            .with_device(10..20, Arc::new(Mutex::new(Timer(0))))
+            .with_device(19..30, Arc::new(Mutex::new(Timer(0))));
+    }
+}
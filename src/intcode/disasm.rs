@@ -0,0 +1,275 @@
+use super::instruction::immediate_write;
+use super::{Instruction, Opcode, ParamMode};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A line of a disassembled program, starting at `addr`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ListingLine {
+    Instruction {
+        addr: usize,
+        words: Vec<i64>,
+        instruction: Instruction,
+    },
+    Data {
+        addr: usize,
+        words: Vec<i64>,
+    },
+}
+
+/// Annotated listing of a program, as returned by [`disassemble`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Listing {
+    pub lines: Vec<ListingLine>,
+}
+
+const DATA_WORDS_PER_LINE: usize = 8;
+
+impl fmt::Display for ListingLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Instruction {
+                addr,
+                words,
+                instruction,
+            } => {
+                let text = format_instruction(*instruction, words);
+                let raw: Vec<String> = words.iter().map(|x| x.to_string()).collect();
+
+                // the assembler has no way to write an immediate write
+                // operand, so these are listed as data to assemble back the same
+                if immediate_write(words[0], instruction.opcode).is_some() {
+                    let data = format!(".data {}", raw.join(", "));
+                    return write!(f, "{:04}: {:<32}; {} (immediate write)", addr, data, text);
+                }
+
+                write!(f, "{:04}: {:<32}; {}", addr, text, raw.join(" "))
+            }
+            Self::Data { addr, words } => {
+                let values: Vec<String> = words.iter().map(|x| x.to_string()).collect();
+                write!(f, "{:04}: .data {}", addr, values.join(", "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes an instruction in assembly, like `add [4], #3, [4]`. `words` starts
+/// with the opcode word.
+pub(crate) fn format_instruction(instruction: Instruction, words: &[i64]) -> String {
+    let operands: Vec<String> = words[1..]
+        .iter()
+        .enumerate()
+        .map(|(i, raw)| {
+            let is_write = instruction.opcode.modes_mask()[i].is_some();
+            format_operand(*raw, instruction.modes[i], is_write)
+        })
+        .collect();
+
+    format!("{} {}", instruction.opcode.mnemonic(), operands.join(", "))
+        .trim_end()
+        .to_string()
+}
+
+fn format_operand(raw: i64, mode: ParamMode, is_write: bool) -> String {
+    match mode {
+        ParamMode::Relative if raw < 0 => format!("rb{}", raw),
+        ParamMode::Relative => format!("rb+{}", raw),
+        ParamMode::Immediate if !is_write => format!("#{}", raw),
+        _ => format!("[{}]", raw),
+    }
+}
+
+/// Where execution can go after an instruction, as far as can be told without
+/// running the program.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Successors {
+    /// The next instruction, unless the instruction halts or always jumps.
+    pub(crate) next: Option<usize>,
+    /// The target of a jump that may be taken, if it's in immediate mode.
+    pub(crate) target: Option<usize>,
+    /// Whether the instruction may jump through position or relative mode,
+    /// to an address that's only known at run time.
+    pub(crate) dynamic: bool,
+}
+
+pub(crate) fn successors(program: &[i64], addr: usize, instruction: Instruction) -> Successors {
+    let next = addr + 1 + instruction.opcode.arity();
+
+    match instruction.opcode {
+        Opcode::Halt => Successors {
+            next: None,
+            target: None,
+            dynamic: false,
+        },
+        Opcode::JumpNotZero | Opcode::JumpZero => {
+            // an immediate condition makes the branch unconditional, as in the
+            // `jnz #1, #addr` idiom
+            let always_taken = match instruction.modes[0] {
+                ParamMode::Immediate => {
+                    Some((program[addr + 1] != 0) == (instruction.opcode == Opcode::JumpNotZero))
+                }
+                _ => None,
+            };
+            let may_jump = always_taken != Some(false);
+            let immediate = instruction.modes[1] == ParamMode::Immediate;
+
+            Successors {
+                next: (always_taken != Some(true)).then_some(next),
+                target: match may_jump && immediate {
+                    true => usize::try_from(program[addr + 2]).ok(),
+                    false => None,
+                },
+                dynamic: may_jump && !immediate,
+            }
+        }
+        _ => Successors {
+            next: Some(next),
+            target: None,
+            dynamic: false,
+        },
+    }
+}
+
+/// Finds the instructions reachable from address 0 by following fall-through
+/// and immediate-mode jump targets, skipping branches that an immediate
+/// condition rules out. Jumps through position or relative mode
+/// can't be resolved statically, so only their fall-through is followed.
+pub(crate) fn reachable_instructions(program: &[i64]) -> BTreeMap<usize, Instruction> {
+    let mut found = BTreeMap::new();
+    let mut pending = vec![0];
+
+    while let Some(addr) = pending.pop() {
+        if addr >= program.len() || found.contains_key(&addr) {
+            continue;
+        }
+        let Ok(instruction) = Instruction::try_from(program[addr]) else {
+            continue;
+        };
+        let next = addr + 1 + instruction.opcode.arity();
+        if next > program.len() {
+            continue;
+        }
+
+        found.insert(addr, instruction);
+        let successors = successors(program, addr, instruction);
+        pending.extend(successors.next);
+        pending.extend(successors.target);
+    }
+
+    found
+}
+
+/// Decodes a program into an annotated listing. Cells that aren't reachable
+/// as code are shown as `.data`.
+pub fn disassemble(program: &[i64]) -> Listing {
+    let code = reachable_instructions(program);
+    let mut lines = vec![];
+    let mut addr = 0;
+
+    while addr < program.len() {
+        if let Some(instruction) = code.get(&addr) {
+            let len = 1 + instruction.opcode.arity();
+            lines.push(ListingLine::Instruction {
+                addr,
+                words: program[addr..addr + len].to_vec(),
+                instruction: *instruction,
+            });
+            addr += len;
+            continue;
+        }
+
+        let end = (addr..program.len())
+            .find(|x| code.contains_key(x) || x - addr == DATA_WORDS_PER_LINE)
+            .unwrap_or(program.len());
+        lines.push(ListingLine::Data {
+            addr,
+            words: program[addr..end].to_vec(),
+        });
+        addr = end;
+    }
+
+    Listing { lines }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let listing = disassemble(&[1002, 4, 3, 4, 33]);
+        assert_eq!(
+            listing.lines,
+            vec![
+                ListingLine::Instruction {
+                    addr: 0,
+                    words: vec![1002, 4, 3, 4],
+                    instruction: Instruction::try_from(1002).unwrap(),
+                },
+                ListingLine::Data {
+                    addr: 4,
+                    words: vec![33],
+                }
+            ]
+        );
+        assert_eq!(
+            listing.to_string(),
+            [
+                "0000: mul [4], #3, [4]                ; 1002 4 3 4\n",
+                "0004: .data 33\n",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_disassemble_follows_immediate_jumps() {
+        let listing = disassemble(&[1105, 1, 5, 7, 7, 204, -3, 99]);
+        let lines: Vec<String> = listing.lines.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0000: jnz #1, #5                      ; 1105 1 5",
+                "0003: .data 7, 7",
+                "0005: out rb-3                        ; 204 -3",
+                "0007: hlt                             ; 99",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_immediate_write_as_data() {
+        let listing = disassemble(&[11101, 1, 1, 5, 99]);
+        assert_eq!(
+            listing.to_string(),
+            [
+                "0000: .data 11101, 1, 1, 5            ; add #1, #1, [5] (immediate write)\n",
+                "0004: hlt                             ; 99\n",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_disassemble_formats_operand_modes() {
+        let listing = disassemble(&[21201, 3, -5, 7, 203, 1, 99]);
+        let lines: Vec<String> = listing.lines.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0000: add rb+3, #-5, rb+7             ; 21201 3 -5 7",
+                "0004: in rb+1                         ; 203 1",
+                "0006: hlt                             ; 99",
+            ]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+
+/// Everything that can make the VM fail. Each variant carries the `ip` of the
+/// instruction that failed and its raw, undecoded word.
+#[derive(Debug, PartialEq, Clone)]
+pub enum VmError {
+    UnknownOpcode {
+        ip: usize,
+        instruction: i64,
+        opcode: i64,
+    },
+    BadMode {
+        ip: usize,
+        instruction: i64,
+        mode: i64,
+    },
+    /// A write parameter encoded in immediate mode, under strict decoding.
+    ImmediateWrite {
+        ip: usize,
+        instruction: i64,
+        param: usize,
+    },
+    /// An opcode extension asked for a parameter that an instruction word
+    /// can't hold the mode of.
+    BadParam {
+        ip: usize,
+        instruction: i64,
+        param: usize,
+    },
+    OutOfBounds {
+        ip: usize,
+        instruction: i64,
+        address: usize,
+    },
+    NegativeAddress {
+        ip: usize,
+        instruction: i64,
+        address: i64,
+    },
+    InputExhausted {
+        ip: usize,
+        instruction: i64,
+    },
+    Io {
+        ip: usize,
+        instruction: i64,
+        message: String,
+    },
+    Overflow {
+        ip: usize,
+        instruction: i64,
+    },
+    InstructionLimit {
+        ip: usize,
+        instruction: i64,
+        limit: u64,
+    },
+    DeadlineExceeded {
+        ip: usize,
+        instruction: i64,
+    },
+    InfiniteLoop {
+        ip: usize,
+        instruction: i64,
+    },
+}
+
+impl VmError {
+    pub fn ip(&self) -> usize {
+        match self {
+            Self::UnknownOpcode { ip, .. }
+            | Self::BadMode { ip, .. }
+            | Self::ImmediateWrite { ip, .. }
+            | Self::BadParam { ip, .. }
+            | Self::OutOfBounds { ip, .. }
+            | Self::NegativeAddress { ip, .. }
+            | Self::InputExhausted { ip, .. }
+            | Self::Io { ip, .. }
+            | Self::Overflow { ip, .. }
+            | Self::InstructionLimit { ip, .. }
+            | Self::DeadlineExceeded { ip, .. }
+            | Self::InfiniteLoop { ip, .. } => *ip,
+        }
+    }
+
+    pub fn instruction(&self) -> i64 {
+        match self {
+            Self::UnknownOpcode { instruction, .. }
+            | Self::BadMode { instruction, .. }
+            | Self::ImmediateWrite { instruction, .. }
+            | Self::BadParam { instruction, .. }
+            | Self::OutOfBounds { instruction, .. }
+            | Self::NegativeAddress { instruction, .. }
+            | Self::InputExhausted { instruction, .. }
+            | Self::Io { instruction, .. }
+            | Self::Overflow { instruction, .. }
+            | Self::InstructionLimit { instruction, .. }
+            | Self::DeadlineExceeded { instruction, .. }
+            | Self::InfiniteLoop { instruction, .. } => *instruction,
+        }
+    }
+
+    pub(crate) fn overflow() -> Self {
+        Self::Overflow {
+            ip: 0,
+            instruction: 0,
+        }
+    }
+
+    /// Errors raised while decoding or accessing memory don't know which
+    /// instruction they belong to; the VM fills that in with this.
+    pub(crate) fn at(self, at_ip: usize, word: i64) -> Self {
+        match self {
+            Self::UnknownOpcode { opcode, .. } => Self::UnknownOpcode {
+                ip: at_ip,
+                instruction: word,
+                opcode,
+            },
+            Self::BadMode { mode, .. } => Self::BadMode {
+                ip: at_ip,
+                instruction: word,
+                mode,
+            },
+            Self::ImmediateWrite { param, .. } => Self::ImmediateWrite {
+                ip: at_ip,
+                instruction: word,
+                param,
+            },
+            Self::BadParam { param, .. } => Self::BadParam {
+                ip: at_ip,
+                instruction: word,
+                param,
+            },
+            Self::OutOfBounds { address, .. } => Self::OutOfBounds {
+                ip: at_ip,
+                instruction: word,
+                address,
+            },
+            Self::NegativeAddress { address, .. } => Self::NegativeAddress {
+                ip: at_ip,
+                instruction: word,
+                address,
+            },
+            Self::InputExhausted { .. } => Self::InputExhausted {
+                ip: at_ip,
+                instruction: word,
+            },
+            Self::Io { message, .. } => Self::Io {
+                ip: at_ip,
+                instruction: word,
+                message,
+            },
+            Self::Overflow { .. } => Self::Overflow {
+                ip: at_ip,
+                instruction: word,
+            },
+            Self::InstructionLimit { limit, .. } => Self::InstructionLimit {
+                ip: at_ip,
+                instruction: word,
+                limit,
+            },
+            Self::DeadlineExceeded { .. } => Self::DeadlineExceeded {
+                ip: at_ip,
+                instruction: word,
+            },
+            Self::InfiniteLoop { .. } => Self::InfiniteLoop {
+                ip: at_ip,
+                instruction: word,
+            },
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownOpcode { opcode, .. } => write!(f, "unknown opcode {}", opcode)?,
+            Self::BadMode { mode, .. } => write!(f, "unrecognized param mode {}", mode)?,
+            Self::ImmediateWrite { param, .. } => {
+                write!(f, "write parameter {} in immediate mode", param)?
+            }
+            Self::BadParam { param, .. } => write!(f, "no mode for parameter {}", param)?,
+            Self::OutOfBounds { address, .. } => write!(f, "address {} out of bounds", address)?,
+            Self::NegativeAddress { address, .. } => write!(f, "negative address {}", address)?,
+            Self::InputExhausted { .. } => write!(f, "input exhausted")?,
+            Self::Io { message, .. } => write!(f, "I/O error: {}", message)?,
+            Self::Overflow { .. } => write!(f, "arithmetic overflow")?,
+            Self::InstructionLimit { limit, .. } => {
+                write!(f, "instruction limit of {} reached", limit)?
+            }
+            Self::DeadlineExceeded { .. } => write!(f, "deadline exceeded")?,
+            Self::InfiniteLoop { .. } => write!(f, "infinite loop detected")?,
+        }
+
+        write!(f, " (instruction {} at {})", self.instruction(), self.ip())
+    }
+}
+
+impl std::error::Error for VmError {}
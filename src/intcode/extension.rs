@@ -0,0 +1,309 @@
+use super::{ParamMode, RunState, VmError, VM};
+use std::fmt;
+use std::sync::Arc;
+
+/// An instruction added to the VM on top of the standard set, for experiments
+/// such as a debug print or a call into the host.
+///
+/// Extensions are shared between clones of a VM, and VMs can be moved to other
+/// threads, so any state an extension keeps needs interior mutability that is
+/// `Send + Sync`, like a `Mutex`.
+pub trait OpcodeExtension: Send + Sync {
+    /// The opcode number, which must not be taken by a standard instruction.
+    fn code(&self) -> i64;
+
+    /// Number of parameters following the opcode word.
+    fn arity(&self) -> usize;
+
+    /// Runs the instruction. Unless the handler calls
+    /// [`ExtensionContext::jump`], execution continues right after its
+    /// parameters. Returning a state pauses the VM as if a standard
+    /// instruction had produced it: in particular, [`RunState::NeedsInput`]
+    /// leaves `ip` on the instruction, so that it runs again once there's
+    /// input. Return it before changing anything.
+    fn exec(&self, ctx: &mut ExtensionContext) -> Result<Option<RunState>, VmError>;
+}
+
+/// What an [`OpcodeExtension`] can see and do while it runs.
+pub struct ExtensionContext<'a> {
+    vm: &'a mut VM,
+    word: i64,
+    jump: Option<usize>,
+}
+
+impl<'a> ExtensionContext<'a> {
+    pub(crate) fn new(vm: &'a mut VM, word: i64) -> Self {
+        Self {
+            vm,
+            word,
+            jump: None,
+        }
+    }
+
+    pub(crate) fn jump_target(&self) -> Option<usize> {
+        self.jump
+    }
+
+    pub fn ip(&self) -> usize {
+        self.vm.ip()
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.vm.relative_base()
+    }
+
+    /// Mode of the 1-based parameter `param`, as encoded in the opcode word.
+    pub fn mode(&self, param: usize) -> Result<ParamMode, VmError> {
+        let divisor = u32::try_from(param)
+            .ok()
+            .filter(|x| *x > 0)
+            .and_then(|x| 10_i64.checked_pow(x + 1))
+            .ok_or(VmError::BadParam {
+                ip: 0,
+                instruction: 0,
+                param,
+            })?;
+        ParamMode::try_from(self.word / divisor % 10)
+    }
+
+    /// Reads the 1-based parameter `param`, resolving its mode.
+    pub fn read_param(&mut self, param: usize) -> Result<i64, VmError> {
+        let mode = self.mode(param)?;
+        let value = self.vm.read_param(param, mode)?;
+        i64::try_from(value).map_err(|_| VmError::overflow())
+    }
+
+    /// Writes `value` to the address the 1-based parameter `param` points at.
+    /// Like with standard instructions, immediate mode is taken as position.
+    pub fn write_param(&mut self, param: usize, value: i64) -> Result<(), VmError> {
+        let mode = self.mode(param)?;
+        let addr = self.vm.write_param(param, mode)?;
+        self.vm.store(addr, value.into())
+    }
+
+    pub fn read_mem(&self, addr: usize) -> Result<i64, VmError> {
+        self.vm.read_mem(addr)
+    }
+
+    pub fn write_mem(&mut self, addr: usize, value: i64) -> Result<(), VmError> {
+        self.vm.store(addr, value.into())
+    }
+
+    /// Takes the next input value, or `None` if there's none yet.
+    pub fn read_input(&mut self) -> Option<i64> {
+        self.vm.pop_input()
+    }
+
+    pub fn jump(&mut self, addr: usize) {
+        self.jump = Some(addr);
+    }
+}
+
+/// The extensions registered on a VM.
+#[derive(Clone, Default)]
+pub(crate) struct Extensions(Vec<Arc<dyn OpcodeExtension>>);
+
+impl Extensions {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn get(&self, code: i64) -> Option<Arc<dyn OpcodeExtension>> {
+        self.0.iter().find(|x| x.code() == code).cloned()
+    }
+
+    pub(crate) fn push(&mut self, extension: Arc<dyn OpcodeExtension>) {
+        self.0.push(extension);
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|x| x.code()))
+            .finish()
+    }
+}
+
+impl PartialEq for Extensions {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && self.0.iter().zip(&other.0).all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Opcode;
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// `dbg a`: appends the value of its parameter to a log.
+    struct DebugPrint(Mutex<Vec<i64>>);
+
+    impl OpcodeExtension for DebugPrint {
+        fn code(&self) -> i64 {
+            42
+        }
+
+        fn arity(&self) -> usize {
+            1
+        }
+
+        fn exec(&self, ctx: &mut ExtensionContext) -> Result<Option<RunState>, VmError> {
+            let value = ctx.read_param(1)?;
+            self.0.lock().unwrap().push(value);
+            Ok(None)
+        }
+    }
+
+    /// `max a, b, c`: stores the larger of `a` and `b` in `c` and outputs it.
+    struct Max;
+
+    impl OpcodeExtension for Max {
+        fn code(&self) -> i64 {
+            50
+        }
+
+        fn arity(&self) -> usize {
+            3
+        }
+
+        fn exec(&self, ctx: &mut ExtensionContext) -> Result<Option<RunState>, VmError> {
+            let max = ctx.read_param(1)?.max(ctx.read_param(2)?);
+            ctx.write_param(3, max)?;
+            Ok(Some(RunState::Output(max)))
+        }
+    }
+
+    #[test]
+    fn test_extensions() {
+        let log = Arc::new(DebugPrint(Mutex::new(vec![])));
+        let mut vm = VM::new(&[142, 7, 42, 8, 150, 8, 9, 10, 99, -3, 0])
+            .with_extension(log.clone())
+            .with_extension(Arc::new(Max));
+
+        let mut output = vec![];
+        vm.run(&mut VecDeque::new(), &mut output).unwrap();
+        assert_eq!(*log.0.lock().unwrap(), vec![7, 99]);
+        assert_eq!(output, vec![8]);
+        assert_eq!(vm.read_mem(10), Ok(8));
+    }
+
+    #[test]
+    fn test_extension_jump() {
+        struct Goto;
+
+        impl OpcodeExtension for Goto {
+            fn code(&self) -> i64 {
+                77
+            }
+
+            fn arity(&self) -> usize {
+                1
+            }
+
+            fn exec(&self, ctx: &mut ExtensionContext) -> Result<Option<RunState>, VmError> {
+                let target = ctx.read_param(1)?;
+                ctx.jump(target as usize);
+                Ok(None)
+            }
+        }
+
+        let mut vm = VM::new(&[177, 4, 104, 1, 104, 2, 99]).with_extension(Arc::new(Goto));
+        assert_eq!(vm.resume(), Ok(RunState::Output(2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "opcode 2 is already taken")]
+    fn test_extension_cannot_replace_standard_opcodes() {
+        struct Mul;
+
+        impl OpcodeExtension for Mul {
+            fn code(&self) -> i64 {
+                Opcode::Mul.code()
+            }
+
+            fn arity(&self) -> usize {
+                3
+            }
+
+            fn exec(&self, _: &mut ExtensionContext) -> Result<Option<RunState>, VmError> {
+                Ok(None)
+            }
+        }
+
+        VM::new(&[99]).with_extension(Arc::new(Mul));
+    }
+
+    #[test]
+    fn test_extension_bad_param() {
+        struct Far;
+
+        impl OpcodeExtension for Far {
+            fn code(&self) -> i64 {
+                60
+            }
+
+            fn arity(&self) -> usize {
+                0
+            }
+
+            fn exec(&self, ctx: &mut ExtensionContext) -> Result<Option<RunState>, VmError> {
+                ctx.read_param(40)?;
+                Ok(None)
+            }
+        }
+
+        let mut vm = VM::new(&[104, 1, 60]).with_extension(Arc::new(Far));
+        assert_eq!(vm.resume(), Ok(RunState::Output(1)));
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::BadParam {
+                ip: 2,
+                instruction: 60,
+                param: 40
+            })
+        );
+    }
+
+    #[test]
+    fn test_extension_needs_input() {
+        /// `sum a`: adds up input values until a 0 and stores the sum in `a`.
+        struct Sum;
+
+        impl OpcodeExtension for Sum {
+            fn code(&self) -> i64 {
+                70
+            }
+
+            fn arity(&self) -> usize {
+                1
+            }
+
+            fn exec(&self, ctx: &mut ExtensionContext) -> Result<Option<RunState>, VmError> {
+                let Some(x) = ctx.read_input() else {
+                    return Ok(Some(RunState::NeedsInput));
+                };
+                let sum = ctx.read_param(1)? + x;
+                ctx.write_param(1, sum)?;
+                if x != 0 {
+                    ctx.jump(ctx.ip());
+                }
+                Ok(None)
+            }
+        }
+
+        let mut vm = VM::new(&[70, 5, 4, 5, 99, 0]).with_extension(Arc::new(Sum));
+        assert_eq!(vm.resume(), Ok(RunState::NeedsInput));
+        assert_eq!(vm.ip(), 0);
+
+        vm.push_input(3);
+        vm.push_input(4);
+        assert_eq!(vm.resume(), Ok(RunState::NeedsInput));
+        assert_eq!(vm.ip(), 0);
+
+        vm.push_input(0);
+        assert_eq!(vm.resume(), Ok(RunState::Output(7)));
+    }
+}
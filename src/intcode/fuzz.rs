@@ -0,0 +1,513 @@
+//! Differential fuzzing: random well-formed programs are run on [`VM`], in a
+//! few configurations, and on a small reference interpreter written to be
+//! obviously correct rather than fast. Any difference in output, final memory,
+//! relative base, or in how and where the run ended is a bug in one of them.
+//!
+//! Failing cases are shrunk before being reported, by dropping cells and
+//! inputs and moving values towards zero for as long as the case keeps
+//! failing.
+
+use super::{VmError, VM};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+/// Instructions either interpreter executes before giving up.
+pub const STEP_LIMIT: u64 = 1_000;
+
+/// Cells after the code that generated programs read and write.
+const DATA_CELLS: usize = 8;
+
+/// A program and the input fed to it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Case {
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let program: Vec<String> = self.program.iter().map(|x| x.to_string()).collect();
+        let input: Vec<String> = self.input.iter().map(|x| x.to_string()).collect();
+        write!(
+            f,
+            "program: {}\ninput: {}",
+            program.join(","),
+            input.join(",")
+        )
+    }
+}
+
+/// How a run ended, without the details that only one interpreter knows.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Fault {
+    UnknownOpcode,
+    BadMode,
+    NegativeAddress,
+    InputExhausted,
+    Overflow,
+    InstructionLimit,
+    Other,
+}
+
+impl From<&VmError> for Fault {
+    fn from(error: &VmError) -> Self {
+        match error {
+            VmError::UnknownOpcode { .. } => Self::UnknownOpcode,
+            VmError::BadMode { .. } => Self::BadMode,
+            VmError::NegativeAddress { .. } => Self::NegativeAddress,
+            VmError::InputExhausted { .. } => Self::InputExhausted,
+            VmError::Overflow { .. } => Self::Overflow,
+            VmError::InstructionLimit { .. } => Self::InstructionLimit,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Everything a run is compared on.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Outcome {
+    pub output: Vec<i64>,
+    /// Non-zero cells of the final memory.
+    pub memory: BTreeMap<usize, i64>,
+    pub memory_len: usize,
+    pub relative_base: i64,
+    /// Where and why the run failed, or `None` if it halted.
+    pub fault: Option<(usize, Fault)>,
+}
+
+/// A case on which a VM configuration and the reference interpreter disagree.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mismatch {
+    pub case: Case,
+    pub config: &'static str,
+    pub expected: Outcome,
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} VM disagrees with the reference on", self.config)?;
+        writeln!(f, "{}", self.case)?;
+        writeln!(f, "expected: {:?}", self.expected)?;
+        write!(f, "actual:   {:?}", self.actual)
+    }
+}
+
+/// A name for a VM configuration, and how to set it up.
+type Config = (&'static str, fn(VM) -> VM);
+
+/// The VM configurations that are checked, which should all behave the same.
+const CONFIGS: [Config; 3] = [
+    ("plain", |vm| vm),
+    ("predecoded", VM::predecoded),
+    ("instrumented", |vm| {
+        vm.with_trace().with_profiler().with_history(16)
+    }),
+];
+
+/// Runs `runs` random cases, starting from `seed`, and returns the first
+/// mismatch found, shrunk.
+pub fn fuzz(seed: u64, runs: usize) -> Result<(), Box<Mismatch>> {
+    let mut rng = Rng::new(seed);
+
+    for _ in 0..runs {
+        let case = generate(&mut rng);
+        if check(&case).is_err() {
+            let case = shrink(case, |x| check(x).is_err());
+            return check(&case);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs a case on every VM configuration and compares each with the
+/// reference interpreter.
+pub fn check(case: &Case) -> Result<(), Box<Mismatch>> {
+    let expected = reference(case);
+
+    for (config, configure) in CONFIGS {
+        let actual = run_vm(case, configure);
+        if actual != expected {
+            return Err(Box::new(Mismatch {
+                case: case.clone(),
+                config,
+                expected,
+                actual,
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+fn run_vm(case: &Case, configure: fn(VM) -> VM) -> Outcome {
+    let mut vm = configure(VM::new(&case.program).with_instruction_limit(STEP_LIMIT));
+    let mut output = vec![];
+    let result = vm.run(&mut VecDeque::from(case.input.clone()), &mut output);
+
+    let snapshot = vm.snapshot();
+    let memory = snapshot
+        .memory
+        .iter()
+        .flat_map(|(start, values)| values.iter().enumerate().map(move |(i, x)| (start + i, *x)))
+        .filter(|(_, x)| *x != 0)
+        .collect();
+
+    Outcome {
+        output,
+        memory,
+        memory_len: snapshot.memory_len,
+        relative_base: vm.relative_base(),
+        fault: result.err().map(|x| (x.ip(), Fault::from(&x))),
+    }
+}
+
+/// Runs a case on the reference interpreter.
+pub fn reference(case: &Case) -> Outcome {
+    let mut machine = Reference {
+        memory: case.program.iter().copied().enumerate().collect(),
+        memory_len: case.program.len(),
+        ip: 0,
+        relative_base: 0,
+        input: case.input.iter().copied().collect(),
+        output: vec![],
+    };
+
+    let mut steps = 0;
+    let fault = loop {
+        if machine.ip >= machine.memory_len {
+            break None;
+        }
+        if steps >= STEP_LIMIT {
+            break Some(Fault::InstructionLimit);
+        }
+        match machine.step() {
+            Ok(true) => break None,
+            Ok(false) => steps += 1,
+            Err(fault) => break Some(fault),
+        }
+    };
+
+    Outcome {
+        fault: fault.map(|x| (machine.ip, x)),
+        memory: machine
+            .memory
+            .into_iter()
+            .filter(|(_, x)| *x != 0)
+            .collect(),
+        memory_len: machine.memory_len,
+        relative_base: machine.relative_base,
+        output: machine.output,
+    }
+}
+
+/// The reference interpreter: Intcode as described in the puzzles, with the
+/// VM's rules for running off the end of memory and for malformed words.
+struct Reference {
+    memory: BTreeMap<usize, i64>,
+    memory_len: usize,
+    ip: usize,
+    relative_base: i64,
+    input: VecDeque<i64>,
+    output: Vec<i64>,
+}
+
+impl Reference {
+    fn cell(&self, addr: usize) -> i64 {
+        self.memory.get(&addr).copied().unwrap_or(0)
+    }
+
+    /// Address the `n`th parameter points at.
+    fn address(&self, word: i64, n: usize) -> Result<usize, Fault> {
+        let raw = self.cell(self.ip + n);
+        let addr = match mode(word, n) {
+            2 => self.relative_base.checked_add(raw).ok_or(Fault::Overflow)?,
+            _ => raw,
+        };
+        usize::try_from(addr).map_err(|_| Fault::NegativeAddress)
+    }
+
+    fn read(&self, word: i64, n: usize) -> Result<i64, Fault> {
+        match mode(word, n) {
+            1 => Ok(self.cell(self.ip + n)),
+            _ => Ok(self.cell(self.address(word, n)?)),
+        }
+    }
+
+    fn write(&mut self, addr: usize, value: i64) {
+        self.memory.insert(addr, value);
+        self.memory_len = self.memory_len.max(addr + 1);
+    }
+
+    /// Executes one instruction. Returns whether it was `hlt`.
+    fn step(&mut self) -> Result<bool, Fault> {
+        let word = self.cell(self.ip);
+        if !matches!(word % 100, 1..=9 | 99) {
+            return Err(Fault::UnknownOpcode);
+        }
+        if (1..=3).any(|n| !(0..=2).contains(&mode(word, n))) {
+            return Err(Fault::BadMode);
+        }
+
+        match word % 100 {
+            1 | 2 | 7 | 8 => {
+                let (a, b) = (self.read(word, 1)?, self.read(word, 2)?);
+                let addr = self.address(word, 3)?;
+                let value = match word % 100 {
+                    1 => a.checked_add(b).ok_or(Fault::Overflow)?,
+                    2 => a.checked_mul(b).ok_or(Fault::Overflow)?,
+                    7 => (a < b) as i64,
+                    _ => (a == b) as i64,
+                };
+                self.write(addr, value);
+                self.ip += 4;
+            }
+            3 => {
+                let value = self.input.pop_front().ok_or(Fault::InputExhausted)?;
+                let addr = self.address(word, 1)?;
+                self.write(addr, value);
+                self.ip += 2;
+            }
+            4 => {
+                let value = self.read(word, 1)?;
+                self.output.push(value);
+                self.ip += 2;
+            }
+            5 | 6 => {
+                let (value, target) = (self.read(word, 1)?, self.read(word, 2)?);
+                self.ip = match (value != 0) == (word % 100 == 5) {
+                    true => usize::try_from(target).map_err(|_| Fault::NegativeAddress)?,
+                    false => self.ip + 3,
+                };
+            }
+            9 => {
+                let value = self.read(word, 1)?;
+                self.relative_base = self
+                    .relative_base
+                    .checked_add(value)
+                    .ok_or(Fault::Overflow)?;
+                self.ip += 2;
+            }
+            _ => return Ok(true),
+        }
+
+        Ok(false)
+    }
+}
+
+/// Mode digit of the `n`th parameter. Write parameters in immediate mode are
+/// taken as position mode by [`Reference::address`].
+fn mode(word: i64, n: usize) -> i64 {
+    word / 10_i64.pow(n as u32 + 1) % 10
+}
+
+/// Generates a random program: a handful of instructions with valid opcodes
+/// and modes, followed by a few data cells, and some input for it. Operands
+/// mostly point at the data cells, but sometimes at the code itself, and
+/// jumps mostly land on instructions.
+pub fn generate(rng: &mut Rng) -> Case {
+    let mut opcodes: Vec<i64> = (0..rng.below(10) + 1)
+        .map(|_| [1, 2, 3, 4, 5, 6, 7, 8, 9][rng.below(9) as usize])
+        .collect();
+    if rng.chance(4, 5) {
+        opcodes.push(99);
+    }
+
+    let mut starts = vec![];
+    let mut len = 0;
+    for opcode in &opcodes {
+        starts.push(len as i64);
+        len += 1 + arity(*opcode);
+    }
+    let size = (len + DATA_CELLS) as i64;
+
+    let mut program = vec![];
+    for opcode in opcodes {
+        let mut word = opcode;
+        let mut operands = vec![];
+
+        for n in 1..=arity(opcode) {
+            let writes = matches!((opcode, n), (1 | 2 | 7 | 8, 3) | (3, 1));
+            let mode = match rng.below(10) {
+                0..=4 => 0,
+                5..=7 if !writes || rng.chance(1, 4) => 1,
+                _ => 2,
+            };
+            word += mode * 10_i64.pow(n as u32 + 1);
+
+            let operand = match mode {
+                1 if matches!(opcode, 5 | 6) && n == 2 => match rng.chance(9, 10) {
+                    true => starts[rng.below(starts.len() as u64) as usize],
+                    false => rng.range(-2, size + 2),
+                },
+                1 if rng.chance(1, 20) => [i64::MAX, i64::MIN, i64::MAX / 2][rng.below(3) as usize],
+                1 => rng.range(-10, 10),
+                2 => rng.range(-3, DATA_CELLS as i64),
+                _ if rng.chance(1, 8) => rng.range(0, len as i64),
+                _ => rng.range(len as i64, size),
+            };
+            operands.push(operand);
+        }
+
+        program.push(word);
+        program.extend(operands);
+    }
+    program.extend((0..DATA_CELLS).map(|_| rng.range(-5, 5)));
+
+    let input = (0..rng.below(4)).map(|_| rng.range(-5, 5)).collect();
+    Case { program, input }
+}
+
+fn arity(opcode: i64) -> usize {
+    match opcode {
+        1 | 2 | 7 | 8 => 3,
+        5 | 6 => 2,
+        3 | 4 | 9 => 1,
+        _ => 0,
+    }
+}
+
+/// Makes `case` as small as possible while `fails` still holds for it.
+pub fn shrink(mut case: Case, fails: impl Fn(&Case) -> bool) -> Case {
+    while let Some(smaller) = smaller_cases(&case).into_iter().find(|x| fails(x)) {
+        case = smaller;
+    }
+
+    case
+}
+
+/// Simpler variations of a case, the most drastic ones first. Each of them is
+/// strictly smaller, so shrinking always ends.
+fn smaller_cases(case: &Case) -> Vec<Case> {
+    // runs of up to four cells first, which is as long as an instruction gets
+    let without_cells = (1..=4).rev().flat_map(|len| {
+        (0..case.program.len().saturating_sub(len - 1)).map(move |i| {
+            let mut program = case.program.clone();
+            program.drain(i..i + len);
+            Case {
+                program,
+                input: case.input.clone(),
+            }
+        })
+    });
+
+    let without_input = (0..case.input.len()).map(|i| {
+        let mut input = case.input.clone();
+        input.remove(i);
+        Case {
+            program: case.program.clone(),
+            input,
+        }
+    });
+
+    let simpler_cell = (0..case.program.len()).flat_map(|i| {
+        let value = case.program[i];
+        [0, value / 2]
+            .into_iter()
+            .filter(move |x| x.unsigned_abs() < value.unsigned_abs())
+            .map(move |x| {
+                let mut program = case.program.clone();
+                program[i] = x;
+                Case {
+                    program,
+                    input: case.input.clone(),
+                }
+            })
+    });
+
+    without_cells
+        .chain(without_input)
+        .chain(simpler_cell)
+        .collect()
+}
+
+/// A small xorshift generator, so that cases can be reproduced from a seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// A number in `low..high`.
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + self.below(high.abs_diff(low)) as i64
+    }
+
+    /// `true` with a probability of `num / den`.
+    pub fn chance(&mut self, num: u64, den: u64) -> bool {
+        self.below(den) < num
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference() {
+        // the day 5 comparison example: outputs 1 if the input is 8
+        let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        let outcome = reference(&Case {
+            program: program.clone(),
+            input: vec![8],
+        });
+        assert_eq!(outcome.output, vec![1]);
+        assert_eq!(outcome.fault, None);
+
+        let outcome = reference(&Case {
+            program,
+            input: vec![],
+        });
+        assert_eq!(outcome.fault, Some((0, Fault::InputExhausted)));
+
+        let outcome = reference(&Case {
+            program: vec![1105, 1, 0],
+            input: vec![],
+        });
+        assert_eq!(outcome.fault, Some((0, Fault::InstructionLimit)));
+    }
+
+    #[test]
+    fn test_vm_matches_reference() {
+        if let Err(mismatch) = fuzz(2019, 2_000) {
+            panic!("{}", mismatch);
+        }
+    }
+
+    #[test]
+    fn test_shrink() {
+        let case = Case {
+            program: vec![3, 13, 1001, 13, 30, 14, 1002, 14, 3, 14, 4, 14, 99, 0, 0],
+            input: vec![7, 8],
+        };
+        let outputs_big_value = |case: &Case| reference(case).output.iter().any(|x| *x > 100);
+        assert!(outputs_big_value(&case));
+
+        let shrunk = shrink(case, outputs_big_value);
+        assert!(outputs_big_value(&shrunk));
+        // the add writes `out [0]` right after itself, which outputs the 1001
+        assert_eq!(
+            shrunk,
+            Case {
+                program: vec![1001, 0, 3, 4],
+                input: vec![],
+            }
+        );
+    }
+}
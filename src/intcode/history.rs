@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+
+/// What an executed instruction changed, so that it can be undone.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Undo {
+    pub(crate) ip: usize,
+    pub(crate) relative_base: i64,
+    pub(crate) memory_len: usize,
+    /// The values cells held before each write, in the order they were written.
+    pub(crate) writes: Vec<(usize, i128)>,
+    pub(crate) input: Option<i64>,
+}
+
+/// Undo log of the last executed instructions. Like with the tracer, a step is
+/// only kept once its instruction finished executing.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct History {
+    steps: VecDeque<Undo>,
+    capacity: usize,
+    current: Option<Undo>,
+}
+
+impl History {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            steps: VecDeque::new(),
+            capacity,
+            current: None,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.steps.clear();
+        self.current = None;
+    }
+
+    pub(crate) fn begin(&mut self, ip: usize, relative_base: i64, memory_len: usize) {
+        self.current = Some(Undo {
+            ip,
+            relative_base,
+            memory_len,
+            writes: vec![],
+            input: None,
+        });
+    }
+
+    pub(crate) fn write(&mut self, addr: usize, old: i128) {
+        if let Some(step) = self.current.as_mut() {
+            step.writes.push((addr, old));
+        }
+    }
+
+    pub(crate) fn input(&mut self, value: i64) {
+        if let Some(step) = self.current.as_mut() {
+            step.input = Some(value);
+        }
+    }
+
+    pub(crate) fn commit(&mut self) {
+        if let Some(step) = self.current.take() {
+            if self.steps.len() == self.capacity {
+                self.steps.pop_front();
+            }
+            if self.capacity > 0 {
+                self.steps.push_back(step);
+            }
+        }
+    }
+
+    pub(crate) fn discard(&mut self) {
+        self.current = None;
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Undo> {
+        self.steps.pop_back()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.steps.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RunState, VM};
+
+    // in [13], add [13], #-3, [14], jz [14], #11, out [14], hlt, 0, 0
+    const PROGRAM: [i64; 15] = [3, 13, 1001, 13, -3, 14, 1006, 14, 11, 4, 14, 99, 0, 0, 0];
+
+    #[test]
+    fn test_step_back() {
+        let mut vm = VM::new(&PROGRAM).with_history(100);
+        vm.push_input(5);
+        let start = vm.clone();
+
+        assert_eq!(vm.resume(), Ok(RunState::Output(2)));
+        assert_eq!(vm.history_len(), 4);
+
+        assert!(vm.step_back());
+        assert_eq!(vm.ip(), 9);
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert_eq!((vm.ip(), vm.read_mem(14)), (2, Ok(0)));
+        assert_eq!(vm.executed(), 1);
+
+        // the input goes back in the queue, so running again takes the same path
+        assert!(vm.step_back());
+        assert_eq!(vm.pending_input(), &[5]);
+        assert!(!vm.step_back());
+        assert_eq!(vm, start);
+
+        assert_eq!(vm.resume(), Ok(RunState::Output(2)));
+    }
+
+    #[test]
+    fn test_run_back_to() {
+        let mut vm = VM::new(&PROGRAM).with_history(2);
+        vm.push_input(3);
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+
+        assert!(vm.run_back_to(6));
+        assert_eq!(vm.read_mem(14), Ok(0));
+
+        // only the last two steps are remembered
+        assert!(!vm.run_back_to(0));
+        assert_eq!(vm.ip(), 6);
+    }
+
+    #[test]
+    fn test_step_back_shrinks_memory() {
+        let mut vm = VM::new(&[1101, 1, 2, 9, 99]).with_history(10);
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+        assert_eq!(vm.read_mem(9), Ok(3));
+
+        assert!(vm.run_back_to(0));
+        assert_eq!(vm.read_mem(9), Ok(0));
+        assert_eq!(vm.snapshot().memory_len, 5);
+    }
+}
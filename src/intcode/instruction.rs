@@ -0,0 +1,273 @@
+use super::VmError;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParamMode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl TryFrom<i64> for ParamMode {
+    type Error = VmError;
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Position),
+            1 => Ok(Self::Immediate),
+            2 => Ok(Self::Relative),
+            _ => Err(VmError::BadMode {
+                ip: 0,
+                instruction: value,
+                mode: value,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Opcode {
+    Add,
+    Mul,
+    Halt,
+    Input,
+    Output,
+    JumpNotZero,
+    JumpZero,
+    Less,
+    Equal,
+    AdjustRelativeBase,
+}
+
+impl TryFrom<i64> for Opcode {
+    type Error = VmError;
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::Add),
+            2 => Ok(Self::Mul),
+            3 => Ok(Self::Input),
+            4 => Ok(Self::Output),
+            5 => Ok(Self::JumpNotZero),
+            6 => Ok(Self::JumpZero),
+            7 => Ok(Self::Less),
+            8 => Ok(Self::Equal),
+            9 => Ok(Self::AdjustRelativeBase),
+            99 => Ok(Self::Halt),
+            _ => Err(VmError::UnknownOpcode {
+                ip: 0,
+                instruction: value,
+                opcode: value,
+            }),
+        }
+    }
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 10] = [
+        Self::Add,
+        Self::Mul,
+        Self::Input,
+        Self::Output,
+        Self::JumpNotZero,
+        Self::JumpZero,
+        Self::Less,
+        Self::Equal,
+        Self::AdjustRelativeBase,
+        Self::Halt,
+    ];
+
+    pub fn code(&self) -> i64 {
+        match self {
+            Self::Add => 1,
+            Self::Mul => 2,
+            Self::Input => 3,
+            Self::Output => 4,
+            Self::JumpNotZero => 5,
+            Self::JumpZero => 6,
+            Self::Less => 7,
+            Self::Equal => 8,
+            Self::AdjustRelativeBase => 9,
+            Self::Halt => 99,
+        }
+    }
+
+    pub fn modes_mask(&self) -> [Option<ParamMode>; 3] {
+        match self {
+            Self::Add => [None, None, Some(ParamMode::Immediate)],
+            Self::Mul => [None, None, Some(ParamMode::Immediate)],
+            Self::Input => [Some(ParamMode::Immediate), None, None],
+            Self::Less => [None, None, Some(ParamMode::Immediate)],
+            Self::Equal => [None, None, Some(ParamMode::Immediate)],
+            _ => [None, None, None],
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Mul => "mul",
+            Self::Halt => "hlt",
+            Self::Input => "in",
+            Self::Output => "out",
+            Self::JumpNotZero => "jnz",
+            Self::JumpZero => "jz",
+            Self::Less => "lt",
+            Self::Equal => "eq",
+            Self::AdjustRelativeBase => "arb",
+        }
+    }
+
+    /// Number of parameters following the opcode word.
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::Halt => 0,
+            Self::Input | Self::Output | Self::AdjustRelativeBase => 1,
+            Self::JumpNotZero | Self::JumpZero => 2,
+            Self::Add | Self::Mul | Self::Less | Self::Equal => 3,
+        }
+    }
+}
+
+/// Divisors that bring each parameter's mode digit down to the units.
+const MODE_DIVISORS: [i64; 3] = [100, 1_000, 10_000];
+
+/// The mode digit of the parameter at index `i` in an instruction word.
+pub(crate) fn raw_mode(value: i64, i: usize) -> i64 {
+    value / MODE_DIVISORS[i] % 10
+}
+
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub modes: [ParamMode; 3],
+}
+
+impl TryFrom<i64> for Instruction {
+    type Error = VmError;
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        let opcode = Opcode::try_from(value % 100).map_err(|x| x.at(0, value))?;
+        let mut modes = [ParamMode::Position; 3];
+
+        for (i, mode) in modes.iter_mut().enumerate() {
+            let raw_mode = raw_mode(value, i);
+            let unmasked_mode = ParamMode::try_from(raw_mode).map_err(|x| x.at(0, value))?;
+            // write params are addresses: relative mode still needs the base
+            // offset applied, every other mode is taken literally
+            *mode = match (opcode.modes_mask()[i], unmasked_mode) {
+                (Some(_), ParamMode::Relative) => ParamMode::Relative,
+                (Some(mask), _) => mask,
+                (None, mode) => mode,
+            };
+        }
+
+        Ok(Self { opcode, modes })
+    }
+}
+
+impl Instruction {
+    /// Decodes like [`Instruction::try_from`], but instead of ignoring a write
+    /// parameter encoded in immediate mode, rejects it.
+    pub fn decode_strict(value: i64) -> Result<Self, VmError> {
+        let instruction = Self::try_from(value)?;
+
+        match immediate_write(value, instruction.opcode) {
+            Some(param) => Err(VmError::ImmediateWrite {
+                ip: 0,
+                instruction: value,
+                param,
+            }),
+            None => Ok(instruction),
+        }
+    }
+}
+
+/// The 1-based position of the first write parameter whose mode digit says
+/// immediate, if any.
+pub(crate) fn immediate_write(value: i64, opcode: Opcode) -> Option<usize> {
+    (0..3)
+        .find(|&i| opcode.modes_mask()[i].is_some() && raw_mode(value, i) == 1)
+        .map(|i| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_opcode() {
+        assert_eq!(Opcode::try_from(99), Ok(Opcode::Halt));
+        assert!(Opcode::try_from(0).is_err());
+    }
+
+    #[test]
+    fn test_parse_instruction_unmasked() {
+        assert_eq!(
+            Instruction::try_from(1004),
+            Ok(Instruction {
+                opcode: Opcode::Output,
+                modes: [
+                    ParamMode::Position,
+                    ParamMode::Immediate,
+                    ParamMode::Position
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction_masked() {
+        assert_eq!(
+            Instruction::try_from(1001),
+            Ok(Instruction {
+                opcode: Opcode::Add,
+                modes: [
+                    ParamMode::Position,
+                    ParamMode::Immediate,
+                    ParamMode::Immediate,
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_strict() {
+        assert_eq!(
+            Instruction::decode_strict(1001),
+            Instruction::try_from(1001)
+        );
+        assert_eq!(
+            Instruction::decode_strict(11101),
+            Err(VmError::ImmediateWrite {
+                ip: 0,
+                instruction: 11101,
+                param: 3
+            })
+        );
+        assert!(Instruction::decode_strict(103).is_err());
+        assert!(Instruction::decode_strict(203).is_ok());
+    }
+
+    #[test]
+    fn test_parse_instruction_relative_write() {
+        assert_eq!(
+            Instruction::try_from(21101),
+            Ok(Instruction {
+                opcode: Opcode::Add,
+                modes: [
+                    ParamMode::Immediate,
+                    ParamMode::Immediate,
+                    ParamMode::Relative,
+                ]
+            })
+        );
+        assert_eq!(
+            Instruction::try_from(203),
+            Ok(Instruction {
+                opcode: Opcode::Input,
+                modes: [
+                    ParamMode::Relative,
+                    ParamMode::Position,
+                    ParamMode::Position,
+                ]
+            })
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc;
+
+/// A source of values for the program's input instructions.
+pub trait IntcodeInput {
+    /// Returns the next value, or `None` once the input is exhausted.
+    fn read(&mut self) -> io::Result<Option<i64>>;
+}
+
+/// A sink for the values of the program's output instructions.
+pub trait IntcodeOutput {
+    fn write(&mut self, value: i64) -> io::Result<()>;
+}
+
+impl IntcodeInput for VecDeque<i64> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        Ok(self.pop_front())
+    }
+}
+
+impl IntcodeOutput for VecDeque<i64> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        self.push_back(value);
+        Ok(())
+    }
+}
+
+impl IntcodeOutput for Vec<i64> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        self.push(value);
+        Ok(())
+    }
+}
+
+impl<F: FnMut() -> Option<i64>> IntcodeInput for F {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        Ok(self())
+    }
+}
+
+impl<F: FnMut(i64)> IntcodeOutput for F {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        self(value);
+        Ok(())
+    }
+}
+
+/// Feeds the values yielded by an iterator.
+#[derive(Debug, Clone)]
+pub struct IterInput<I>(pub I);
+
+impl<I: Iterator<Item = i64>> IntcodeInput for IterInput<I> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        Ok(self.0.next())
+    }
+}
+
+/// Blocks until a value arrives. A disconnected channel counts as exhausted
+/// input.
+impl IntcodeInput for mpsc::Receiver<i64> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        Ok(self.recv().ok())
+    }
+}
+
+impl IntcodeOutput for mpsc::Sender<i64> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        self.send(value)
+            .map_err(|x| io::Error::new(io::ErrorKind::BrokenPipe, x))
+    }
+}
+
+/// Reads one decimal value per line from a text stream.
+#[derive(Debug)]
+pub struct TextInput<R>(pub R);
+
+impl<R: io::BufRead> IntcodeInput for TextInput<R> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        let mut buffer = String::new();
+        loop {
+            buffer.clear();
+            let n = self.0.read_line(&mut buffer)?;
+
+            if n == 0 {
+                return Ok(None);
+            }
+            if !buffer.trim().is_empty() {
+                break;
+            }
+        }
+
+        buffer.trim().parse().map(Some).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid input: {}", buffer.trim()),
+            )
+        })
+    }
+}
+
+/// Writes one decimal value per line to a text stream.
+#[derive(Debug)]
+pub struct TextOutput<W>(pub W);
+
+impl<W: io::Write> IntcodeOutput for TextOutput<W> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        writeln!(self.0, "{}", value)
+    }
+}
+
+/// Feeds each line of a text stream as its character codes followed by a
+/// newline (10), for programs that talk in ASCII.
+#[derive(Debug)]
+pub struct AsciiInput<R> {
+    reader: R,
+    pending: VecDeque<i64>,
+}
+
+impl<R> AsciiInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: io::BufRead> IntcodeInput for AsciiInput<R> {
+    fn read(&mut self) -> io::Result<Option<i64>> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.pending = encode_line(line.trim_end_matches(['\r', '\n']))?.into();
+        }
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// The character codes of `line` followed by a newline. Fails if the line has
+/// characters outside ASCII.
+pub(crate) fn encode_line(line: &str) -> io::Result<Vec<i64>> {
+    if !line.is_ascii() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("input isn't ASCII: {}", line),
+        ));
+    }
+
+    Ok(line.bytes().map(i64::from).chain([10]).collect())
+}
+
+/// Writes values that are ASCII codes as characters, and anything else, like
+/// the answers programs print at the end, as a decimal number on its own line.
+#[derive(Debug)]
+pub struct AsciiOutput<W>(pub W);
+
+impl<W: io::Write> IntcodeOutput for AsciiOutput<W> {
+    fn write(&mut self, value: i64) -> io::Result<()> {
+        match u8::try_from(value) {
+            Ok(byte) if byte.is_ascii() => self.0.write_all(&[byte]),
+            _ => writeln!(self.0, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{VmError, VM};
+    use super::*;
+
+    #[test]
+    fn test_run_with_queues() {
+        let mut output: Vec<i64> = vec![];
+        let mut vm = VM::new(&[3, 0, 3, 1, 4, 1, 4, 0, 99]);
+        vm.run(&mut VecDeque::from([7, 8]), &mut output).unwrap();
+        assert_eq!(output, vec![8, 7]);
+    }
+
+    #[test]
+    fn test_run_with_closures_and_iterators() {
+        let mut sum = 0;
+        let mut vm = VM::new(&[3, 0, 3, 1, 4, 1, 4, 0, 99]);
+        vm.run(&mut IterInput(2..), &mut |x| sum += x).unwrap();
+        assert_eq!(sum, 5);
+
+        let mut next = 10;
+        let mut output = VecDeque::new();
+        let mut vm = VM::new(&[3, 0, 3, 1, 4, 1, 4, 0, 99]);
+        vm.run(
+            &mut || {
+                next += 1;
+                Some(next)
+            },
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, VecDeque::from([12, 11]));
+    }
+
+    #[test]
+    fn test_run_with_channels() {
+        let (input_tx, mut input_rx) = mpsc::channel();
+        let (mut output_tx, output_rx) = mpsc::channel();
+
+        let handle = std::thread::spawn(move || {
+            let mut vm = VM::new(&[3, 0, 4, 0, 3, 0, 4, 0, 99]);
+            vm.run(&mut input_rx, &mut output_tx)
+        });
+
+        input_tx.send(4).unwrap();
+        assert_eq!(output_rx.recv(), Ok(4));
+        input_tx.send(2).unwrap();
+        assert_eq!(output_rx.recv(), Ok(2));
+        assert_eq!(handle.join().unwrap(), Ok(2));
+    }
+
+    #[test]
+    fn test_run_with_ascii() {
+        // echoes a line, then prints 1000
+        let program = [
+            3, 100, 4, 100, 1008, 100, 10, 101, 1006, 101, 0, 104, 1000, 99,
+        ];
+        let mut output = AsciiOutput(vec![]);
+        let mut vm = VM::new(&program);
+        vm.run(
+            &mut AsciiInput::new("hi\r\nthere\n".as_bytes()),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output.0).unwrap(), "hi\n1000\n");
+
+        let mut vm = VM::new(&program);
+        let error = vm
+            .run(&mut AsciiInput::new("¡hola!".as_bytes()), &mut vec![])
+            .unwrap_err();
+        assert_eq!(
+            error,
+            VmError::Io {
+                ip: 0,
+                instruction: 3,
+                message: "input isn't ASCII: ¡hola!".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_io_error() {
+        let mut vm = VM::new(&[3, 0, 99]);
+        let error = vm
+            .run(&mut TextInput("abc".as_bytes()), &mut vec![])
+            .unwrap_err();
+        assert_eq!(
+            error,
+            VmError::Io {
+                ip: 0,
+                instruction: 3,
+                message: "invalid input: abc".to_string()
+            }
+        );
+    }
+}
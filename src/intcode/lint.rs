@@ -0,0 +1,72 @@
+use super::disasm::reachable_instructions;
+use super::instruction::immediate_write;
+use super::Opcode;
+use std::fmt;
+
+/// A write parameter encoded in immediate mode. The VM runs these as if they
+/// were in position mode unless strict decoding is on, but they usually point
+/// at a bug in whatever produced the program.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LintWarning {
+    pub addr: usize,
+    pub word: i64,
+    pub opcode: Opcode,
+    /// 1-based position of the offending parameter.
+    pub param: usize,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}: write parameter {} of {} ({}) is in immediate mode",
+            self.addr,
+            self.param,
+            self.opcode.mnemonic(),
+            self.word
+        )
+    }
+}
+
+/// Checks every instruction reachable from the start of the program, as found
+/// by the disassembler, for write parameters encoded in immediate mode.
+pub fn lint(program: &[i64]) -> Vec<LintWarning> {
+    reachable_instructions(program)
+        .into_iter()
+        .filter_map(|(addr, instruction)| {
+            let word = program[addr];
+            immediate_write(word, instruction.opcode).map(|param| LintWarning {
+                addr,
+                word,
+                opcode: instruction.opcode,
+                param,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        assert_eq!(lint(&[1101, 1, 2, 0, 99]), vec![]);
+
+        // the 1103 after `hlt` is data and doesn't get reported
+        let warnings = lint(&[3, 0, 11101, 1, 2, 3, 99, 1103]);
+        assert_eq!(
+            warnings,
+            vec![LintWarning {
+                addr: 2,
+                word: 11101,
+                opcode: Opcode::Add,
+                param: 3
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "0002: write parameter 3 of add (11101) is in immediate mode"
+        );
+    }
+}
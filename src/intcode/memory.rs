@@ -0,0 +1,276 @@
+use super::VmError;
+use std::collections::HashMap;
+
+const PAGE_SIZE: usize = 1024;
+
+/// Intcode memory: reads past the end of the program yield zero and writes
+/// grow it on demand.
+///
+/// Pages next to the program are kept in a dense vector, while writes to far
+/// away addresses land in sparse pages so that they don't allocate everything
+/// in between.
+///
+/// Cells holding values that don't fit an `i64`, which only wide arithmetic
+/// produces, live in a separate table and read as zero in the pages.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub(crate) struct Memory {
+    dense: Vec<i64>,
+    sparse: HashMap<usize, Vec<i64>>,
+    wide: HashMap<usize, i128>,
+    len: usize,
+    pub(crate) limit: Option<usize>,
+}
+
+impl From<&[i64]> for Memory {
+    fn from(src: &[i64]) -> Self {
+        let mut dense = src.to_owned();
+        dense.resize(src.len().div_ceil(PAGE_SIZE) * PAGE_SIZE, 0);
+
+        Self {
+            dense,
+            sparse: HashMap::new(),
+            wide: HashMap::new(),
+            len: src.len(),
+            limit: None,
+        }
+    }
+}
+
+impl Memory {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Fails with an overflow if the cell holds a wide value.
+    pub fn get(&self, addr: usize) -> Result<i64, VmError> {
+        self.check_limit(addr)?;
+
+        if !self.wide.is_empty() && self.wide.contains_key(&addr) {
+            return Err(VmError::overflow());
+        }
+
+        if let Some(value) = self.dense.get(addr) {
+            return Ok(*value);
+        }
+
+        Ok(self
+            .sparse
+            .get(&(addr / PAGE_SIZE))
+            .map(|page| page[addr % PAGE_SIZE])
+            .unwrap_or(0))
+    }
+
+    pub fn get_wide(&self, addr: usize) -> Result<i128, VmError> {
+        if !self.wide.is_empty() {
+            if let Some(value) = self.wide.get(&addr) {
+                return Ok(*value);
+            }
+        }
+
+        self.get(addr).map(i128::from)
+    }
+
+    pub fn set(&mut self, addr: usize, value: i64) -> Result<(), VmError> {
+        self.check_limit(addr)?;
+
+        if !self.wide.is_empty() {
+            self.wide.remove(&addr);
+        }
+
+        let page = addr / PAGE_SIZE;
+        let dense_pages = self.dense.len() / PAGE_SIZE;
+
+        if page == dense_pages {
+            self.grow_dense();
+        }
+
+        if let Some(cell) = self.dense.get_mut(addr) {
+            *cell = value;
+        } else {
+            self.sparse
+                .entry(page)
+                .or_insert_with(|| vec![0; PAGE_SIZE])[addr % PAGE_SIZE] = value;
+        }
+
+        self.len = self.len.max(addr + 1);
+        Ok(())
+    }
+
+    /// Stores values that fit an `i64` in the pages like [`Memory::set`], and
+    /// anything bigger in the wide table.
+    pub fn set_wide(&mut self, addr: usize, value: i128) -> Result<(), VmError> {
+        match i64::try_from(value) {
+            Ok(value) => self.set(addr, value),
+            Err(_) => {
+                self.set(addr, 0)?;
+                self.wide.insert(addr, value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Shrinks memory back to `len` cells, after the ones past it have been
+    /// reset to zero.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Cells holding values too big for an `i64`, in address order.
+    pub(crate) fn wide_cells(&self) -> Vec<(usize, i128)> {
+        let mut cells: Vec<_> = self.wide.iter().map(|(k, v)| (*k, *v)).collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    /// The non-zero stretches of memory, as `(start address, values)` pairs in
+    /// address order.
+    pub(crate) fn segments(&self) -> Vec<(usize, Vec<i64>)> {
+        let dense = &self.dense[..self.len.min(self.dense.len())];
+        let mut pages: Vec<_> = self.sparse.iter().collect();
+        pages.sort_unstable_by_key(|(page, _)| **page);
+
+        std::iter::once((0, dense))
+            .chain(
+                pages
+                    .into_iter()
+                    .map(|(page, x)| (page * PAGE_SIZE, &x[..])),
+            )
+            .filter_map(|(start, values)| {
+                let end = values.iter().rposition(|x| *x != 0)? + 1;
+                Some((start, values[..end].to_vec()))
+            })
+            .collect()
+    }
+
+    /// Rebuilds memory out of [`Memory::segments`] and [`Memory::wide_cells`].
+    pub(crate) fn from_segments(
+        len: usize,
+        segments: &[(usize, Vec<i64>)],
+        wide: &[(usize, i128)],
+        limit: Option<usize>,
+    ) -> Result<Self, VmError> {
+        let mut memory = Self {
+            limit,
+            ..Self::default()
+        };
+
+        for (start, values) in segments {
+            for (i, value) in values.iter().enumerate() {
+                memory.set(start + i, *value)?;
+            }
+        }
+        for (addr, value) in wide {
+            memory.set_wide(*addr, *value)?;
+        }
+
+        if let Some(last) = len.checked_sub(1) {
+            memory.check_limit(last)?;
+        }
+        memory.len = memory.len.max(len);
+        Ok(memory)
+    }
+
+    fn check_limit(&self, addr: usize) -> Result<(), VmError> {
+        match self.limit {
+            Some(limit) if addr >= limit => Err(VmError::OutOfBounds {
+                ip: 0,
+                instruction: 0,
+                address: addr,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends a page to the dense region, then absorbs whatever sparse pages
+    /// have become contiguous with it.
+    fn grow_dense(&mut self) {
+        self.dense.resize(self.dense.len() + PAGE_SIZE, 0);
+
+        while let Some(page) = self.sparse.remove(&(self.dense.len() / PAGE_SIZE)) {
+            self.dense.extend(page);
+        }
+    }
+}
+
+pub(crate) fn to_address(value: i64) -> Result<usize, VmError> {
+    usize::try_from(value).map_err(|_| VmError::NegativeAddress {
+        ip: 0,
+        instruction: 0,
+        address: value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_reads_zero_past_program() {
+        let memory = Memory::from(&[1, 2, 3][..]);
+        assert_eq!(memory.get(2), Ok(3));
+        assert_eq!(memory.get(3), Ok(0));
+        assert_eq!(memory.get(5_000), Ok(0));
+    }
+
+    #[test]
+    fn test_memory_grows_sparsely() {
+        let mut memory = Memory::from(&[1, 2, 3][..]);
+
+        memory.set(1_000_000, 42).unwrap();
+        assert_eq!(memory.get(1_000_000), Ok(42));
+        assert_eq!(memory.len(), 1_000_001);
+        assert_eq!(memory.dense.len(), PAGE_SIZE);
+        assert_eq!(memory.sparse.len(), 1);
+
+        memory.set(PAGE_SIZE + 1, 7).unwrap();
+        assert_eq!(memory.get(PAGE_SIZE + 1), Ok(7));
+        assert_eq!(memory.dense.len(), 2 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_memory_segments_round_trip() {
+        let mut memory = Memory::from(&[1, 2, 0, 0][..]);
+        memory.set(1_000_000, 42).unwrap();
+        memory.set(5_000, 0).unwrap();
+
+        let segments = memory.segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0], (0, vec![1, 2]));
+        assert_eq!(segments[1].0, 976 * PAGE_SIZE);
+        assert_eq!(segments[1].1.len(), 1_000_001 - 976 * PAGE_SIZE);
+        assert_eq!(segments[1].1.last(), Some(&42));
+
+        let restored = Memory::from_segments(memory.len(), &segments, &[], None).unwrap();
+        assert_eq!(restored.len(), 1_000_001);
+        assert_eq!(restored.get(1), Ok(2));
+        assert_eq!(restored.get(1_000_000), Ok(42));
+        assert_eq!(restored.get(5_000), Ok(0));
+    }
+
+    #[test]
+    fn test_memory_wide_cells() {
+        let mut memory = Memory::from(&[1, 2, 3][..]);
+        let big = i64::MAX as i128 + 1;
+
+        memory.set_wide(1, big).unwrap();
+        assert_eq!(memory.get_wide(1), Ok(big));
+        assert_eq!(memory.get(1), Err(VmError::overflow()));
+        assert_eq!(memory.wide_cells(), vec![(1, big)]);
+
+        memory.set_wide(1, 5).unwrap();
+        assert_eq!(memory.get(1), Ok(5));
+        assert!(memory.wide_cells().is_empty());
+    }
+
+    #[test]
+    fn test_memory_absorbs_contiguous_sparse_pages() {
+        let mut memory = Memory::from(&[1][..]);
+
+        memory.set(2 * PAGE_SIZE, 5).unwrap();
+        memory.set(PAGE_SIZE, 4).unwrap();
+        assert_eq!(memory.dense.len(), 3 * PAGE_SIZE);
+        assert!(memory.sparse.is_empty());
+        assert_eq!(memory.get(PAGE_SIZE), Ok(4));
+        assert_eq!(memory.get(2 * PAGE_SIZE), Ok(5));
+    }
+}
@@ -0,0 +1,191 @@
+//! Execution counters to find out where a program spends its time.
+
+use super::Opcode;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// How often a memory cell was used as an operand or a destination.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct CellAccess {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// A backward jump that was taken: the code in `start..=end` ran again.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Loop {
+    pub start: usize,
+    pub end: usize,
+    pub iterations: u64,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Profiler {
+    opcodes: HashMap<Opcode, u64>,
+    addresses: HashMap<usize, u64>,
+    cells: HashMap<usize, CellAccess>,
+    back_edges: HashMap<(usize, usize), u64>,
+}
+
+impl Profiler {
+    pub(crate) fn instruction(&mut self, ip: usize, opcode: Opcode) {
+        *self.opcodes.entry(opcode).or_default() += 1;
+        *self.addresses.entry(ip).or_default() += 1;
+    }
+
+    pub(crate) fn read(&mut self, addr: usize) {
+        self.cells.entry(addr).or_default().reads += 1;
+    }
+
+    pub(crate) fn write(&mut self, addr: usize) {
+        self.cells.entry(addr).or_default().writes += 1;
+    }
+
+    pub(crate) fn jump(&mut self, from: usize, to: usize) {
+        if to <= from {
+            *self.back_edges.entry((to, from)).or_default() += 1;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.opcodes.values().sum()
+    }
+
+    /// Executions per opcode, most frequent first.
+    pub fn opcodes(&self) -> Vec<(Opcode, u64)> {
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(k, v)| (*k, *v)).collect();
+        opcodes.sort_by_key(|(opcode, count)| (std::cmp::Reverse(*count), opcode.code()));
+        opcodes
+    }
+
+    /// Executions per instruction address, most frequent first.
+    pub fn hot_addresses(&self) -> Vec<(usize, u64)> {
+        hottest(&self.addresses, |x| *x)
+    }
+
+    /// Loops found from taken backward jumps, most iterated first.
+    pub fn hot_loops(&self) -> Vec<Loop> {
+        hottest(&self.back_edges, |x| *x)
+            .into_iter()
+            .map(|((start, end), iterations)| Loop {
+                start,
+                end,
+                iterations,
+            })
+            .collect()
+    }
+
+    /// Reads and writes per memory cell, most accessed first.
+    pub fn hot_cells(&self) -> Vec<(usize, CellAccess)> {
+        hottest(&self.cells, |x| x.reads + x.writes)
+    }
+
+    /// A summary with the `top` entries of each section.
+    pub fn report(&self, top: usize) -> String {
+        let total = self.total();
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+        let mut report = String::new();
+
+        writeln!(report, "{} instructions executed", total).unwrap();
+
+        writeln!(report, "\nopcodes:").unwrap();
+        for (opcode, count) in self.opcodes() {
+            let (mnemonic, share) = (opcode.mnemonic(), percent(count));
+            writeln!(report, "  {:<4} {:>12} {:>6.2}%", mnemonic, count, share).unwrap();
+        }
+
+        writeln!(report, "\nhot loops:").unwrap();
+        for x in self.hot_loops().into_iter().take(top) {
+            let range = format!("{:04}..{:04}", x.start, x.end);
+            writeln!(report, "  {} {:>12} iterations", range, x.iterations).unwrap();
+        }
+
+        writeln!(report, "\nhot instructions:").unwrap();
+        for (addr, count) in self.hot_addresses().into_iter().take(top) {
+            let share = percent(count);
+            writeln!(report, "  {:04} {:>12} {:>6.2}%", addr, count, share).unwrap();
+        }
+
+        writeln!(report, "\nhot cells:").unwrap();
+        for (addr, access) in self.hot_cells().into_iter().take(top) {
+            let (reads, writes) = (access.reads, access.writes);
+            writeln!(
+                report,
+                "  [{:04}] {:>12} reads {:>12} writes",
+                addr, reads, writes
+            )
+            .unwrap();
+        }
+
+        report
+    }
+}
+
+/// Sorts by descending weight, breaking ties by key so reports are stable.
+fn hottest<K: Ord + Copy, V: Copy>(
+    counts: &HashMap<K, V>,
+    weight: impl Fn(&V) -> u64,
+) -> Vec<(K, V)> {
+    let mut entries: Vec<(K, V)> = counts.iter().map(|(k, v)| (*k, *v)).collect();
+    entries.sort_by(|a, b| weight(&b.1).cmp(&weight(&a.1)).then(a.0.cmp(&b.0)));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::VM;
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn profile(src: &[i64]) -> Profiler {
+        let mut vm = VM::new(src).with_profiler();
+        vm.run(&mut VecDeque::new(), &mut vec![]).unwrap();
+        vm.profiler().unwrap().clone()
+    }
+
+    #[test]
+    fn test_counts_a_loop() {
+        // counts [20] from 0 up to 5
+        let src = [1001, 20, 1, 20, 1007, 20, 5, 21, 1005, 21, 0, 99];
+        let profiler = profile(&src);
+
+        assert_eq!(profiler.total(), 16);
+        assert_eq!(
+            profiler.opcodes(),
+            vec![
+                (Opcode::Add, 5),
+                (Opcode::JumpNotZero, 5),
+                (Opcode::Less, 5),
+                (Opcode::Halt, 1)
+            ]
+        );
+        assert_eq!(profiler.hot_addresses()[..2], [(0, 5), (4, 5)]);
+        assert_eq!(
+            profiler.hot_loops(),
+            vec![Loop {
+                start: 0,
+                end: 8,
+                iterations: 4
+            }]
+        );
+
+        let cells: Vec<_> = profiler
+            .hot_cells()
+            .into_iter()
+            .map(|(addr, x)| (addr, x.reads, x.writes))
+            .collect();
+        assert_eq!(cells, vec![(20, 10, 5), (21, 5, 5)]);
+    }
+
+    #[test]
+    fn test_report() {
+        let src = [1001, 20, 1, 20, 1007, 20, 5, 21, 1005, 21, 0, 99];
+        let report = profile(&src).report(1);
+
+        assert!(report
+            .starts_with("16 instructions executed\n\nopcodes:\n  add             5  31.25%\n"));
+        assert!(report.contains("\nhot loops:\n  0000..0008            4 iterations\n\n"));
+        assert!(report.contains("\nhot instructions:\n  0000            5  31.25%\n\n"));
+        assert!(report.ends_with("\nhot cells:\n  [0020]           10 reads            5 writes\n"));
+    }
+}
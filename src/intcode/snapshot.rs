@@ -0,0 +1,252 @@
+//! Saving a [`VM`](super::VM) to disk and loading it back later.
+//!
+//! Snapshots are plain text, one field per line, so they can be inspected and
+//! tweaked by hand when preparing test fixtures:
+//!
+//! ```text
+//! intcode-snapshot 1
+//! ip 4
+//! relative-base 0
+//! profile full
+//! arithmetic checked
+//! memory-limit none
+//! input 5 -3
+//! memory-len 9
+//! memory 0 1101,2,3,0,3,0,4,0,99
+//! ```
+//!
+//! Memory is stored as `memory <start> <values>` runs, leaving out zeros, so a
+//! program that wrote to a far away address doesn't produce a huge file. Cells
+//! too big for an `i64` follow as `wide <address> <value>` lines.
+
+use super::{ArithmeticPolicy, Profile};
+use std::io::{self, BufRead, Write};
+
+const HEADER: &str = "intcode-snapshot";
+const VERSION: u32 = 1;
+
+/// The complete state of a paused VM. Get one with
+/// [`VM::snapshot`](super::VM::snapshot) and turn it back into a VM with
+/// [`VM::from_snapshot`](super::VM::from_snapshot), or load it into an existing
+/// VM, keeping its configuration, with [`VM::restore`](super::VM::restore).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Snapshot {
+    pub ip: usize,
+    pub relative_base: i64,
+    pub profile: Profile,
+    pub arithmetic: ArithmeticPolicy,
+    pub memory_limit: Option<usize>,
+    /// Values pushed with [`VM::push_input`](super::VM::push_input) that the
+    /// program hasn't read yet.
+    pub input: Vec<i64>,
+    pub memory_len: usize,
+    pub memory: Vec<(usize, Vec<i64>)>,
+    pub wide: Vec<(usize, i128)>,
+}
+
+impl Snapshot {
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{} {}", HEADER, VERSION)?;
+        writeln!(out, "ip {}", self.ip)?;
+        writeln!(out, "relative-base {}", self.relative_base)?;
+        writeln!(
+            out,
+            "profile {}",
+            match self.profile {
+                Profile::Full => "full",
+                Profile::Day2 => "day2",
+            }
+        )?;
+        writeln!(
+            out,
+            "arithmetic {}",
+            match self.arithmetic {
+                ArithmeticPolicy::Checked => "checked",
+                ArithmeticPolicy::Wrapping => "wrapping",
+                ArithmeticPolicy::Saturating => "saturating",
+                ArithmeticPolicy::Wide => "wide",
+            }
+        )?;
+        match self.memory_limit {
+            Some(limit) => writeln!(out, "memory-limit {}", limit)?,
+            None => writeln!(out, "memory-limit none")?,
+        }
+        writeln!(out, "input {}", join(&self.input, " "))?;
+        writeln!(out, "memory-len {}", self.memory_len)?;
+        for (start, values) in &self.memory {
+            writeln!(out, "memory {} {}", start, join(values, ","))?;
+        }
+        for (addr, value) in &self.wide {
+            writeln!(out, "wide {} {}", addr, value)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from(input: impl BufRead) -> io::Result<Self> {
+        let mut lines = input.lines().enumerate();
+
+        match lines.next() {
+            Some((_, line)) => match line?.split_once(' ') {
+                Some((HEADER, version)) if version == VERSION.to_string() => {}
+                Some((HEADER, version)) => {
+                    return Err(invalid_data(
+                        1,
+                        &format!("unsupported snapshot version {}", version),
+                    ))
+                }
+                _ => return Err(invalid_data(1, "not an Intcode snapshot")),
+            },
+            None => return Err(invalid_data(1, "empty snapshot")),
+        }
+
+        let mut snapshot = Self {
+            ip: 0,
+            relative_base: 0,
+            profile: Profile::Full,
+            arithmetic: ArithmeticPolicy::Checked,
+            memory_limit: None,
+            input: vec![],
+            memory_len: 0,
+            memory: vec![],
+            wide: vec![],
+        };
+
+        for (i, line) in lines {
+            let line = line?;
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            let error = |message: &str| invalid_data(i + 1, message);
+            let number = |x: &str| {
+                x.parse::<i64>()
+                    .map_err(|_| error(&format!("invalid number {}", x)))
+            };
+            let address = |x: &str| {
+                x.parse::<usize>()
+                    .map_err(|_| error(&format!("invalid address {}", x)))
+            };
+
+            match key {
+                "" => {}
+                "ip" => snapshot.ip = address(value)?,
+                "relative-base" => snapshot.relative_base = number(value)?,
+                "profile" => {
+                    snapshot.profile = match value {
+                        "full" => Profile::Full,
+                        "day2" => Profile::Day2,
+                        _ => return Err(error(&format!("unknown profile {}", value))),
+                    }
+                }
+                "arithmetic" => {
+                    snapshot.arithmetic = match value {
+                        "checked" => ArithmeticPolicy::Checked,
+                        "wrapping" => ArithmeticPolicy::Wrapping,
+                        "saturating" => ArithmeticPolicy::Saturating,
+                        "wide" => ArithmeticPolicy::Wide,
+                        _ => return Err(error(&format!("unknown arithmetic {}", value))),
+                    }
+                }
+                "memory-limit" => {
+                    snapshot.memory_limit = match value {
+                        "none" => None,
+                        _ => Some(address(value)?),
+                    }
+                }
+                "input" => {
+                    snapshot.input = value
+                        .split_whitespace()
+                        .map(number)
+                        .collect::<Result<_, _>>()?
+                }
+                "memory-len" => snapshot.memory_len = address(value)?,
+                "memory" => {
+                    let (start, values) = value
+                        .split_once(' ')
+                        .ok_or_else(|| error("expected a start address and values"))?;
+                    let values = values.split(',').map(number).collect::<Result<_, _>>()?;
+                    snapshot.memory.push((address(start)?, values));
+                }
+                "wide" => {
+                    let (addr, value) = value
+                        .split_once(' ')
+                        .ok_or_else(|| error("expected an address and a value"))?;
+                    let value = value
+                        .parse()
+                        .map_err(|_| error(&format!("invalid number {}", value)))?;
+                    snapshot.wide.push((address(addr)?, value));
+                }
+                _ => return Err(error(&format!("unknown field {}", key))),
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+fn join(values: &[i64], separator: &str) -> String {
+    let values: Vec<String> = values.iter().map(|x| x.to_string()).collect();
+    values.join(separator)
+}
+
+fn invalid_data(line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RunState, VM};
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut vm = VM::new(&[109, 5, 3, 0, 4, 0, 3, 100, 21101, 2, 3, 999_999, 99]);
+        vm.push_input(7);
+        vm.push_input(-8);
+        assert_eq!(vm.resume(), Ok(RunState::Output(7)));
+
+        let mut bytes = vec![];
+        vm.snapshot().write_to(&mut bytes).unwrap();
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        assert!(text.starts_with("intcode-snapshot 1\nip 6\nrelative-base 5\n"));
+        assert!(text.contains("\ninput -8\n"));
+
+        let snapshot = Snapshot::read_from(bytes.as_slice()).unwrap();
+        assert_eq!(snapshot, vm.snapshot());
+
+        let mut restored = VM::from_snapshot(snapshot).unwrap();
+        assert_eq!(restored.snapshot(), vm.snapshot());
+        assert_eq!(restored.resume(), Ok(RunState::Halted));
+        assert_eq!(restored.read_mem(100), Ok(-8));
+        assert_eq!(restored.read_mem(1_000_004), Ok(5));
+    }
+
+    #[test]
+    fn test_snapshot_wide_cells() {
+        let mut vm =
+            VM::new(&[1002, 5, 2, 6, 99, i64::MAX, 0]).with_arithmetic(ArithmeticPolicy::Wide);
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+
+        let mut bytes = vec![];
+        vm.snapshot().write_to(&mut bytes).unwrap();
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        assert!(text.contains("\narithmetic wide\n"));
+        assert!(text.ends_with("\nwide 6 18446744073709551614\n"));
+
+        let restored = VM::from_snapshot(Snapshot::read_from(bytes.as_slice()).unwrap());
+        assert_eq!(restored.unwrap().read_wide(6), vm.read_wide(6));
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let error = Snapshot::read_from("intcode-snapshot 2\n".as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "line 1: unsupported snapshot version 2");
+
+        let error = Snapshot::read_from("intcode-snapshot 1\nip -1\n".as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "line 2: invalid address -1");
+
+        let error = Snapshot::read_from("hello".as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,378 @@
+//! Symbolic execution of the day 2 subset of Intcode: `add`, `mul` and `hlt`,
+//! in position mode only. Some memory cells are taken as unknowns, and values
+//! computed from them are kept as [`Polynomial`]s over those unknowns instead
+//! of numbers.
+//!
+//! Day 2 programs can't jump, so they take the same path whatever the unknowns
+//! are, as long as the unknowns aren't executed or used as addresses to write
+//! to. Reading through an address that depends on the unknowns is fine, but
+//! the value read is lost track of; execution only fails if that value ends up
+//! in the cell asked for.
+
+use super::memory::to_address;
+use super::vm::check_day2;
+use super::{Instruction, Opcode, VmError};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A polynomial with integer coefficients over the unknowns `x0`, `x1`, …
+/// Terms are keyed by the exponent of each unknown.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Polynomial {
+    terms: BTreeMap<Vec<u32>, i128>,
+}
+
+impl Polynomial {
+    pub fn constant(value: i128) -> Self {
+        let mut terms = BTreeMap::new();
+        if value != 0 {
+            terms.insert(vec![], value);
+        }
+        Self { terms }
+    }
+
+    /// The unknown `x{index}`.
+    pub fn unknown(index: usize) -> Self {
+        let mut exponents = vec![0; index + 1];
+        exponents[index] = 1;
+        Self {
+            terms: BTreeMap::from([(exponents, 1)]),
+        }
+    }
+
+    /// The value of the polynomial, if it doesn't depend on any unknown.
+    pub fn as_constant(&self) -> Option<i128> {
+        match self.terms.len() {
+            0 => Some(0),
+            1 => self.terms.get(&vec![]).copied(),
+            _ => None,
+        }
+    }
+
+    /// Coefficient of the term with the given exponents. Trailing zeros can be
+    /// left out, so `&[]` is the constant term and `&[0, 1]` the one of `x1`.
+    pub fn coefficient(&self, exponents: &[u32]) -> i128 {
+        self.terms.get(&trimmed(exponents)).copied().unwrap_or(0)
+    }
+
+    /// Highest power of `x{index}` in the polynomial.
+    pub fn degree_in(&self, index: usize) -> u32 {
+        self.terms
+            .keys()
+            .map(|x| x.get(index).copied().unwrap_or(0))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Replaces `x{index}` with `value`. `None` if a coefficient overflows.
+    pub fn substitute(&self, index: usize, value: i128) -> Option<Self> {
+        let mut result = Self::default();
+        for (exponents, coefficient) in &self.terms {
+            let mut exponents = exponents.clone();
+            let power = match exponents.get_mut(index) {
+                Some(x) => std::mem::take(x),
+                None => 0,
+            };
+            let term = coefficient.checked_mul(value.checked_pow(power)?)?;
+            result.add_term(trimmed(&exponents), term)?;
+        }
+
+        Some(result)
+    }
+
+    /// Evaluates the polynomial. `None` if it overflows, or if it depends on
+    /// unknowns past the end of `values`.
+    pub fn eval(&self, values: &[i128]) -> Option<i128> {
+        self.terms
+            .iter()
+            .try_fold(0_i128, |sum, (exponents, coefficient)| {
+                let term = exponents
+                    .iter()
+                    .enumerate()
+                    .try_fold(*coefficient, |term, (i, power)| {
+                        term.checked_mul(values.get(i)?.checked_pow(*power)?)
+                    })?;
+                sum.checked_add(term)
+            })
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Option<Self> {
+        let mut result = self.clone();
+        for (exponents, coefficient) in &other.terms {
+            result.add_term(exponents.clone(), *coefficient)?;
+        }
+
+        Some(result)
+    }
+
+    pub fn checked_mul(&self, other: &Self) -> Option<Self> {
+        let mut result = Self::default();
+        for (a, x) in &self.terms {
+            for (b, y) in &other.terms {
+                let len = a.len().max(b.len());
+                let exponents = (0..len)
+                    .map(|i| a.get(i).unwrap_or(&0) + b.get(i).unwrap_or(&0))
+                    .collect();
+                result.add_term(exponents, x.checked_mul(*y)?)?;
+            }
+        }
+
+        Some(result)
+    }
+
+    fn add_term(&mut self, exponents: Vec<u32>, coefficient: i128) -> Option<()> {
+        let sum = self
+            .terms
+            .get(&exponents)
+            .unwrap_or(&0)
+            .checked_add(coefficient)?;
+
+        match sum {
+            0 => self.terms.remove(&exponents),
+            _ => self.terms.insert(exponents, sum),
+        };
+        Some(())
+    }
+}
+
+fn trimmed(exponents: &[u32]) -> Vec<u32> {
+    let len = exponents.iter().rposition(|x| *x != 0).map_or(0, |x| x + 1);
+    exponents[..len].to_vec()
+}
+
+impl fmt::Display for Polynomial {
+    /// Writes the highest degree terms first, like `3*x0^2 - x1 + 7`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+
+        let mut terms: Vec<_> = self.terms.iter().collect();
+        terms.sort_by_key(|(exponents, _)| Reverse((exponents.iter().sum::<u32>(), *exponents)));
+
+        for (i, (exponents, coefficient)) in terms.into_iter().enumerate() {
+            let factors: Vec<String> = exponents
+                .iter()
+                .enumerate()
+                .filter(|(_, power)| **power > 0)
+                .map(|(x, power)| match power {
+                    1 => format!("x{}", x),
+                    _ => format!("x{}^{}", x, power),
+                })
+                .collect();
+
+            match (i, *coefficient < 0) {
+                (0, true) => write!(f, "-")?,
+                (0, false) => {}
+                (_, true) => write!(f, " - ")?,
+                (_, false) => write!(f, " + ")?,
+            }
+
+            let magnitude = coefficient.unsigned_abs();
+            match (factors.is_empty(), magnitude) {
+                (true, _) => write!(f, "{}", magnitude)?,
+                (false, 1) => write!(f, "{}", factors.join("*"))?,
+                (false, _) => write!(f, "{}*{}", magnitude, factors.join("*"))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a program couldn't be executed symbolically. Other than
+/// [`SymbolicError::Vm`], these don't mean the program is broken, just that
+/// it needs to be run with actual values.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SymbolicError {
+    /// The word at `ip` was going to be executed but depends on the unknowns.
+    SymbolicInstruction { ip: usize },
+    /// The instruction at `ip` writes to an address that depends on the
+    /// unknowns.
+    SymbolicWrite { ip: usize },
+    /// The result was computed from a cell read through an address that
+    /// depends on the unknowns.
+    LostTrack,
+    /// A coefficient grew too big at the instruction at `ip`.
+    Overflow { ip: usize },
+    /// The program fails the same way whatever the unknowns are.
+    Vm(VmError),
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SymbolicInstruction { ip } => {
+                write!(f, "instruction at {} depends on the unknowns", ip)
+            }
+            Self::SymbolicWrite { ip } => {
+                write!(f, "write address at {} depends on the unknowns", ip)
+            }
+            Self::LostTrack => write!(f, "result was read through an unknown address"),
+            Self::Overflow { ip } => write!(f, "coefficient overflow at {}", ip),
+            Self::Vm(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl std::error::Error for SymbolicError {}
+
+impl From<VmError> for SymbolicError {
+    fn from(error: VmError) -> Self {
+        Self::Vm(error)
+    }
+}
+
+/// Runs a day 2 program with the cells in `unknowns` taken as `x0`, `x1`, …
+/// and returns what ends up in the cell at `result` once it halts.
+///
+/// Like the VM under [`super::Profile::Day2`], memory can't grow past the end
+/// of the program.
+pub fn execute(
+    program: &[i64],
+    unknowns: &[usize],
+    result: usize,
+) -> Result<Polynomial, SymbolicError> {
+    // `None` marks cells whose value was lost track of
+    let mut memory: Vec<Option<Polynomial>> = program
+        .iter()
+        .map(|x| Some(Polynomial::constant((*x).into())))
+        .collect();
+    for (i, addr) in unknowns.iter().enumerate() {
+        *cell(&mut memory, *addr)? = Some(Polynomial::unknown(i));
+    }
+
+    let mut ip = 0;
+    while ip < memory.len() {
+        let word = cell(&mut memory, ip)?
+            .as_ref()
+            .and_then(Polynomial::as_constant)
+            .ok_or(SymbolicError::SymbolicInstruction { ip })?;
+        let word = i64::try_from(word).map_err(|_| VmError::overflow().at(ip, 0))?;
+
+        let at = |x: VmError| x.at(ip, word);
+        let instruction = Instruction::try_from(word).map_err(at)?;
+        check_day2(word, instruction).map_err(at)?;
+
+        let mut address = |offset: usize| -> Result<Option<usize>, VmError> {
+            let param = cell(&mut memory, ip + offset).map_err(at)?;
+            match param.as_ref().and_then(Polynomial::as_constant) {
+                Some(value) => {
+                    let value = i64::try_from(value).map_err(|_| at(VmError::overflow()))?;
+                    to_address(value).map(Some).map_err(at)
+                }
+                None => Ok(None),
+            }
+        };
+
+        let (x, y, z) = match instruction.opcode {
+            Opcode::Halt => break,
+            _ => (address(1)?, address(2)?, address(3)?),
+        };
+        let z = z.ok_or(SymbolicError::SymbolicWrite { ip })?;
+
+        let mut read = |addr: Option<usize>| match addr {
+            Some(addr) => cell(&mut memory, addr).map(|x| x.clone()).map_err(at),
+            None => Ok(None),
+        };
+        let (x, y) = (read(x)?, read(y)?);
+
+        let value = match (x, y) {
+            (Some(x), Some(y)) => {
+                let value = match instruction.opcode {
+                    Opcode::Add => x.checked_add(&y),
+                    _ => x.checked_mul(&y),
+                };
+                Some(value.ok_or(SymbolicError::Overflow { ip })?)
+            }
+            _ => None,
+        };
+        *cell(&mut memory, z).map_err(at)? = value;
+
+        ip += 4;
+    }
+
+    cell(&mut memory, result)?
+        .clone()
+        .ok_or(SymbolicError::LostTrack)
+}
+
+fn cell(
+    memory: &mut [Option<Polynomial>],
+    addr: usize,
+) -> Result<&mut Option<Polynomial>, VmError> {
+    memory.get_mut(addr).ok_or(VmError::OutOfBounds {
+        ip: 0,
+        instruction: 0,
+        address: addr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polynomial() {
+        let (x, y) = (Polynomial::unknown(0), Polynomial::unknown(1));
+        let p = x
+            .checked_mul(&x)
+            .and_then(|x2| x2.checked_mul(&Polynomial::constant(3)))
+            .and_then(|p| p.checked_add(&Polynomial::constant(7)))
+            .and_then(|p| p.checked_add(&y.checked_mul(&Polynomial::constant(-1))?))
+            .unwrap();
+
+        assert_eq!(p.to_string(), "3*x0^2 - x1 + 7");
+        assert_eq!(p.eval(&[2, 5]), Some(14));
+        assert_eq!(p.degree_in(0), 2);
+        assert_eq!(p.coefficient(&[0, 1]), -1);
+        assert_eq!(
+            p.substitute(0, 2),
+            Polynomial::constant(19)
+                .checked_add(&y.checked_mul(&Polynomial::constant(-1)).unwrap())
+        );
+        assert_eq!(
+            p.checked_add(&p.checked_mul(&Polynomial::constant(-1)).unwrap()),
+            Some(Polynomial::default())
+        );
+    }
+
+    #[test]
+    fn test_execute() {
+        // [0] = [9] * [10] + [9]
+        let program = [2, 9, 10, 11, 1, 11, 9, 0, 99, 0, 0, 0];
+        let p = execute(&program, &[9, 10], 0).unwrap();
+        assert_eq!(p.to_string(), "x0*x1 + x0");
+
+        // `add [x0], [x1], [3]` reads through the unknowns, but the result is
+        // overwritten before it's used
+        let program = [1, 0, 0, 3, 1, 1, 2, 0, 99];
+        let p = execute(&program, &[1, 2], 0).unwrap();
+        assert_eq!(p.to_string(), "x0 + x1");
+    }
+
+    #[test]
+    fn test_execute_errors() {
+        assert_eq!(
+            execute(&[1, 0, 0, 0, 99], &[1, 2], 0),
+            Err(SymbolicError::LostTrack)
+        );
+        assert_eq!(
+            execute(&[1, 5, 5, 0, 99, 1], &[3], 0),
+            Err(SymbolicError::SymbolicWrite { ip: 0 })
+        );
+        assert_eq!(
+            execute(&[1, 5, 5, 4, 0, 0], &[5], 0),
+            Err(SymbolicError::SymbolicInstruction { ip: 4 })
+        );
+        assert_eq!(
+            execute(&[3, 0, 99], &[1], 0),
+            Err(SymbolicError::Vm(VmError::UnknownOpcode {
+                ip: 0,
+                instruction: 3,
+                opcode: 3
+            }))
+        );
+    }
+}
@@ -0,0 +1,460 @@
+//! Execution traces: a record of every instruction a [`VM`] executed, with the
+//! values it read, the memory it wrote and the I/O it did.
+//!
+//! Traces are saved in a compact binary format: the magic bytes `ICTR`, a
+//! version byte, the VM settings, and then one record per step. Numbers are
+//! LEB128 varints, and signed ones are zigzag-encoded first. The settings are
+//! a profile byte (`0` full, `1` day 2), an arithmetic byte (`0` checked, `1`
+//! wrapping, `2` saturating, `3` wide) and the memory limit plus one, or `0`
+//! for none. A record holds:
+//!
+//! - `ip` and the raw instruction word,
+//! - the number of operands read, followed by their values,
+//! - the number of memory writes, followed by `address, value` pairs,
+//! - an I/O tag (`0` none, `1` input, `2` output), followed by the value unless
+//!   it's `0`.
+
+use super::{ArithmeticPolicy, Instruction, IterInput, Profile, VmError, VM};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"ICTR";
+const VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IoEvent {
+    Input(i64),
+    Output(i64),
+}
+
+/// A single executed instruction.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraceStep {
+    pub ip: usize,
+    pub word: i64,
+    pub instruction: Instruction,
+    /// Values of the read parameters, after resolving their modes.
+    pub operands: Vec<i128>,
+    pub writes: Vec<(usize, i128)>,
+    pub io: Option<IoEvent>,
+}
+
+impl TraceStep {
+    fn new(ip: usize, word: i64, instruction: Instruction) -> Self {
+        Self {
+            ip,
+            word,
+            instruction,
+            operands: vec![],
+            writes: vec![],
+            io: None,
+        }
+    }
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}: {}", self.ip, self.instruction.opcode.mnemonic())?;
+
+        let operands: Vec<String> = self.operands.iter().map(|x| x.to_string()).collect();
+        if !operands.is_empty() {
+            write!(f, " {}", operands.join(", "))?;
+        }
+
+        for (addr, value) in &self.writes {
+            write!(f, " [{}] <- {}", addr, value)?;
+        }
+
+        match self.io {
+            Some(IoEvent::Input(x)) => write!(f, " ; in {}", x),
+            Some(IoEvent::Output(x)) => write!(f, " ; out {}", x),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    /// Settings of the VM the trace was recorded on, which
+    /// [`Trace::replay`] runs with.
+    pub profile: Profile,
+    pub arithmetic: ArithmeticPolicy,
+    pub memory_limit: Option<usize>,
+}
+
+impl Trace {
+    /// The values the program consumed as input, in order.
+    pub fn inputs(&self) -> Vec<i64> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step.io {
+                Some(IoEvent::Input(x)) => Some(x),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Finds the first step where two traces differ, if any.
+    pub fn diff(&self, other: &Trace) -> Option<Divergence> {
+        let len = self.steps.len().max(other.steps.len());
+
+        (0..len)
+            .find(|&i| self.steps.get(i) != other.steps.get(i))
+            .map(|index| Divergence {
+                index,
+                left: self.steps.get(index).cloned(),
+                right: other.steps.get(index).cloned(),
+            })
+    }
+
+    /// Runs `src` with the inputs and VM settings recorded in this trace and
+    /// reports where its execution departs from the recorded one.
+    pub fn replay(&self, src: &[i64]) -> Result<Option<Divergence>, VmError> {
+        let mut vm = VM::new(src)
+            .with_profile(self.profile)
+            .with_arithmetic(self.arithmetic);
+        if let Some(limit) = self.memory_limit {
+            vm = vm.with_memory_limit(limit);
+        }
+        let mut vm = vm.with_trace();
+
+        match vm.run(&mut IterInput(self.inputs().into_iter()), &mut vec![]) {
+            Ok(_) | Err(VmError::InputExhausted { .. }) => {}
+            Err(x) => return Err(x),
+        }
+
+        Ok(self.diff(&vm.take_trace().unwrap_or_default()))
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        let profile = match self.profile {
+            Profile::Full => 0,
+            Profile::Day2 => 1,
+        };
+        let arithmetic = match self.arithmetic {
+            ArithmeticPolicy::Checked => 0,
+            ArithmeticPolicy::Wrapping => 1,
+            ArithmeticPolicy::Saturating => 2,
+            ArithmeticPolicy::Wide => 3,
+        };
+        out.write_all(&[profile, arithmetic])?;
+        write_varint(out, self.memory_limit.map_or(0, |x| x as u128 + 1))?;
+
+        for step in &self.steps {
+            write_varint(out, step.ip as u128)?;
+            write_signed(out, step.word.into())?;
+
+            write_varint(out, step.operands.len() as u128)?;
+            for x in &step.operands {
+                write_signed(out, *x)?;
+            }
+
+            write_varint(out, step.writes.len() as u128)?;
+            for (addr, value) in &step.writes {
+                write_varint(out, *addr as u128)?;
+                write_signed(out, *value)?;
+            }
+
+            match step.io {
+                None => out.write_all(&[0])?,
+                Some(IoEvent::Input(x)) => {
+                    out.write_all(&[1])?;
+                    write_signed(out, x.into())?;
+                }
+                Some(IoEvent::Output(x)) => {
+                    out.write_all(&[2])?;
+                    write_signed(out, x.into())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not an Intcode trace"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported trace version {}",
+                header[4]
+            )));
+        }
+
+        let profile = match required(read_byte(input)?)? {
+            0 => Profile::Full,
+            1 => Profile::Day2,
+            x => return Err(invalid_data(&format!("unknown profile {}", x))),
+        };
+        let arithmetic = match required(read_byte(input)?)? {
+            0 => ArithmeticPolicy::Checked,
+            1 => ArithmeticPolicy::Wrapping,
+            2 => ArithmeticPolicy::Saturating,
+            3 => ArithmeticPolicy::Wide,
+            x => return Err(invalid_data(&format!("unknown arithmetic {}", x))),
+        };
+        let memory_limit = match required(read_varint(input)?)? {
+            0 => None,
+            x => Some(x as usize - 1),
+        };
+
+        let mut trace = Self {
+            profile,
+            arithmetic,
+            memory_limit,
+            ..Self::default()
+        };
+
+        while let Some(ip) = read_varint(input)? {
+            let word = read_i64(input)?;
+            let instruction =
+                Instruction::try_from(word).map_err(|x| invalid_data(&x.to_string()))?;
+            let mut step = TraceStep::new(ip as usize, word, instruction);
+
+            for _ in 0..required(read_varint(input)?)? {
+                step.operands.push(read_signed(input)?);
+            }
+
+            for _ in 0..required(read_varint(input)?)? {
+                let addr = required(read_varint(input)?)? as usize;
+                step.writes.push((addr, read_signed(input)?));
+            }
+
+            step.io = match required(read_byte(input)?)? {
+                0 => None,
+                1 => Some(IoEvent::Input(read_i64(input)?)),
+                2 => Some(IoEvent::Output(read_i64(input)?)),
+                tag => return Err(invalid_data(&format!("unknown I/O tag {}", tag))),
+            };
+
+            trace.steps.push(step);
+        }
+
+        Ok(trace)
+    }
+}
+
+/// The first step at which two traces disagree. A missing side means that
+/// trace had already ended.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Divergence {
+    pub index: usize,
+    pub left: Option<TraceStep>,
+    pub right: Option<TraceStep>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "traces diverge at step {}", self.index)?;
+        for (sign, step) in [("<", &self.left), (">", &self.right)] {
+            match step {
+                Some(step) => writeln!(f, "{} {}", sign, step)?,
+                None => writeln!(f, "{} (end of trace)", sign)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects the steps of a running VM. A step is only kept once its
+/// instruction finished executing.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub(crate) struct Tracer {
+    pub(crate) trace: Trace,
+    current: Option<TraceStep>,
+}
+
+impl Tracer {
+    pub(crate) fn begin(&mut self, ip: usize, word: i64, instruction: Instruction) {
+        self.current = Some(TraceStep::new(ip, word, instruction));
+    }
+
+    pub(crate) fn operand(&mut self, value: i128) {
+        if let Some(step) = self.current.as_mut() {
+            step.operands.push(value);
+        }
+    }
+
+    pub(crate) fn write(&mut self, addr: usize, value: i128) {
+        if let Some(step) = self.current.as_mut() {
+            step.writes.push((addr, value));
+        }
+    }
+
+    pub(crate) fn io(&mut self, event: IoEvent) {
+        if let Some(step) = self.current.as_mut() {
+            step.io = Some(event);
+        }
+    }
+
+    pub(crate) fn commit(&mut self) {
+        if let Some(step) = self.current.take() {
+            self.trace.steps.push(step);
+        }
+    }
+
+    pub(crate) fn discard(&mut self) {
+        self.current = None;
+    }
+}
+
+fn write_varint(out: &mut impl Write, mut value: u128) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn write_signed(out: &mut impl Write, value: i128) -> io::Result<()> {
+    write_varint(out, ((value << 1) ^ (value >> 127)) as u128)
+}
+
+fn unzigzag(value: u128) -> i128 {
+    ((value >> 1) as i128) ^ -((value & 1) as i128)
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Reads a varint, or `None` if the input ended right before it.
+fn read_varint(input: &mut impl Read) -> io::Result<Option<u128>> {
+    let Some(mut byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+
+    let mut value = 0u128;
+    let mut shift = 0;
+    loop {
+        if shift >= 128 {
+            return Err(invalid_data("varint too long"));
+        }
+        value |= ((byte & 0x7f) as u128) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+        shift += 7;
+        byte = required(read_byte(input)?)?;
+    }
+}
+
+fn read_signed(input: &mut impl Read) -> io::Result<i128> {
+    required(read_varint(input)?).map(unzigzag)
+}
+
+fn read_i64(input: &mut impl Read) -> io::Result<i64> {
+    i64::try_from(read_signed(input)?).map_err(|_| invalid_data("value out of range"))
+}
+
+fn required<T>(value: Option<T>) -> io::Result<T> {
+    value.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "truncated trace"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn trace(src: &[i64], input: &[i64]) -> Trace {
+        let mut vm = VM::new(src).with_trace();
+        vm.run(&mut VecDeque::from(input.to_vec()), &mut vec![])
+            .unwrap();
+        vm.take_trace().unwrap()
+    }
+
+    #[test]
+    fn test_records_steps() {
+        let trace = trace(&[3, 9, 1001, 9, 2, 10, 4, 10, 99, 0, 0], &[5]);
+
+        let lines: Vec<String> = trace.steps.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0000: in [9] <- 5 ; in 5",
+                "0002: add 5, 2 [10] <- 7",
+                "0006: out 7 ; out 7",
+                "0008: hlt",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let trace = trace(
+            &[109, -3, 3, 11, 1002, 11, -300, 12, 204, 15, 99, 0, 0],
+            &[7],
+        );
+
+        let mut bytes = vec![];
+        trace.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..8], b"ICTR\x01\x00\x00\x00");
+        assert_eq!(Trace::read_from(&mut bytes.as_slice()).unwrap(), trace);
+
+        let error = Trace::read_from(&mut &bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let error = Trace::read_from(&mut &b"nope!"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_diff_and_replay() {
+        let src = [3, 9, 1001, 9, 2, 10, 4, 10, 99, 0, 0];
+        let recorded = trace(&src, &[5]);
+        assert_eq!(recorded.diff(&recorded), None);
+        assert_eq!(recorded.replay(&src), Ok(None));
+
+        let patched = [3, 9, 1001, 9, 3, 10, 4, 10, 99, 0, 0];
+        let divergence = recorded.replay(&patched).unwrap().unwrap();
+        assert_eq!(divergence.index, 1);
+        assert_eq!(
+            divergence.to_string(),
+            "traces diverge at step 1\n< 0002: add 5, 2 [10] <- 7\n> 0002: add 5, 3 [10] <- 8\n"
+        );
+
+        let shorter = Trace {
+            steps: recorded.steps[..2].to_vec(),
+            ..Trace::default()
+        };
+        let divergence = shorter.diff(&recorded).unwrap();
+        assert_eq!(divergence.index, 2);
+        assert_eq!(divergence.left, None);
+    }
+
+    #[test]
+    fn test_replay_with_recorded_settings() {
+        // overflows, so it only runs as recorded with wrapping arithmetic
+        let src = [1002, 7, 2, 8, 4, 8, 99, i64::MAX, 0];
+        let mut vm = VM::new(&src)
+            .with_arithmetic(ArithmeticPolicy::Wrapping)
+            .with_memory_limit(16)
+            .with_trace();
+        vm.run(&mut VecDeque::new(), &mut vec![]).unwrap();
+        let recorded = vm.take_trace().unwrap();
+        assert_eq!(recorded.arithmetic, ArithmeticPolicy::Wrapping);
+        assert_eq!(recorded.memory_limit, Some(16));
+
+        let mut bytes = vec![];
+        recorded.write_to(&mut bytes).unwrap();
+        let read = Trace::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, recorded);
+        assert_eq!(read.replay(&src), Ok(None));
+    }
+}
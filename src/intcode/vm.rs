@@ -0,0 +1,1503 @@
+use super::device::Devices;
+use super::disasm::reachable_instructions;
+use super::extension::{ExtensionContext, Extensions};
+use super::history::History;
+use super::instruction::raw_mode;
+use super::memory::{to_address, Memory};
+use super::profiler::Profiler;
+use super::snapshot::Snapshot;
+use super::trace::{IoEvent, Trace, Tracer};
+use super::{
+    Device, Instruction, IntcodeInput, IntcodeOutput, Opcode, OpcodeExtension, ParamMode, VmError,
+};
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Why a [`VM`] stopped running and handed control back to the caller.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RunState {
+    /// An input instruction was reached with no pending input. Feed a value
+    /// with [`VM::push_input`] and resume.
+    NeedsInput,
+    Output(i64),
+    Halted,
+}
+
+/// Which flavour of Intcode the VM accepts.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Profile {
+    /// The complete instruction set, with memory growing on demand.
+    #[default]
+    Full,
+    /// The day 2 subset: `add`, `mul` and `hlt` in position mode only, with
+    /// memory fixed to the size of the program.
+    Day2,
+}
+
+/// What `add` and `mul` do when the result doesn't fit an `i64`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ArithmeticPolicy {
+    /// Fail with [`VmError::Overflow`].
+    #[default]
+    Checked,
+    /// Wrap around in two's complement.
+    Wrapping,
+    /// Clamp to `i64::MIN` or `i64::MAX`.
+    Saturating,
+    /// Keep the exact result in an `i128` cell. Read those cells back with
+    /// [`VM::read_wide`]; using them as an output, address or jump target still
+    /// needs them to fit an `i64`.
+    Wide,
+}
+
+impl ArithmeticPolicy {
+    /// Applies the policy to the exact result of an operation, which is `None`
+    /// if not even an `i128` could hold it.
+    fn apply(self, exact: Option<i128>) -> Result<i128, VmError> {
+        let (min, max) = (i128::from(i64::MIN), i128::from(i64::MAX));
+
+        match self {
+            Self::Checked => exact.filter(|x| (min..=max).contains(x)),
+            Self::Wrapping => exact.map(|x| i128::from(x as i64)),
+            Self::Saturating => exact.map(|x| x.clamp(min, max)),
+            Self::Wide => exact,
+        }
+        .ok_or(VmError::overflow())
+    }
+}
+
+/// Instructions already decoded, by address, so that loops don't decode the
+/// same words over and over. Writing to an address drops its entry, which keeps
+/// self-modifying programs working: only the opcode word is cached, operands
+/// are always read from memory.
+#[derive(Debug, Clone, Default)]
+struct DecodeCache(Vec<Option<Instruction>>);
+
+impl DecodeCache {
+    fn get(&self, addr: usize) -> Option<Instruction> {
+        self.0.get(addr).copied().flatten()
+    }
+
+    fn insert(&mut self, addr: usize, instruction: Instruction) {
+        if addr >= self.0.len() {
+            self.0.resize(addr + 1, None);
+        }
+        self.0[addr] = Some(instruction);
+    }
+
+    fn invalidate(&mut self, addr: usize) {
+        if let Some(entry) = self.0.get_mut(addr) {
+            *entry = None;
+        }
+    }
+}
+
+/// Finds out whether the VM keeps going through the same states, with Brent's
+/// algorithm: each state is compared against one saved earlier state, which
+/// moves forward every time the number of steps since it was saved reaches the
+/// next power of two. Only whole states are compared, so a hit is always a
+/// real repeat, and a single state is kept however long the run goes on.
+#[derive(Debug, PartialEq, Clone)]
+struct LoopDetector {
+    saved: Option<(usize, i64, Memory)>,
+    power: u64,
+    steps: u64,
+}
+
+impl Default for LoopDetector {
+    fn default() -> Self {
+        Self {
+            saved: None,
+            power: 1,
+            steps: 0,
+        }
+    }
+}
+
+impl LoopDetector {
+    /// Returns whether the VM has been in this exact state before.
+    fn repeats(&mut self, ip: usize, relative_base: i64, memory: &Memory) -> bool {
+        if let Some((saved_ip, saved_base, saved_memory)) = &self.saved {
+            // memory is only compared when the cheap parts already match
+            if *saved_ip == ip && *saved_base == relative_base && saved_memory == memory {
+                return true;
+            }
+        }
+
+        self.steps += 1;
+        if self.steps == self.power {
+            self.saved = Some((ip, relative_base, memory.clone()));
+            self.power = self.power.saturating_mul(2);
+            self.steps = 0;
+        }
+        false
+    }
+}
+
+/// The cache never changes what a VM does, so it's left out of comparisons.
+impl PartialEq for DecodeCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VM {
+    ip: usize,
+    relative_base: i64,
+    memory: Memory,
+    decoded: DecodeCache,
+    input: VecDeque<i64>,
+    profile: Profile,
+    arithmetic: ArithmeticPolicy,
+    strict: bool,
+    extensions: Extensions,
+    devices: Devices,
+    executed: u64,
+    /// Set once `hlt` has run, so that resuming a halted VM doesn't run it
+    /// again, counting it against the limits and the loop detector.
+    halted: bool,
+    instruction_limit: Option<u64>,
+    deadline: Option<Instant>,
+    loop_detector: Option<LoopDetector>,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    history: Option<History>,
+}
+
+impl VM {
+    pub fn new(src: &[i64]) -> Self {
+        Self {
+            ip: 0,
+            relative_base: 0,
+            memory: Memory::from(src),
+            decoded: DecodeCache(vec![None; src.len()]),
+            input: VecDeque::new(),
+            profile: Profile::Full,
+            arithmetic: ArithmeticPolicy::Checked,
+            strict: false,
+            extensions: Extensions::default(),
+            devices: Devices::default(),
+            executed: 0,
+            halted: false,
+            instruction_limit: None,
+            deadline: None,
+            loop_detector: None,
+            tracer: None,
+            profiler: None,
+            history: None,
+        }
+    }
+
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.memory.limit = Some(limit);
+        self.record_settings();
+        self
+    }
+
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        if profile == Profile::Day2 {
+            self.memory.limit = Some(self.memory.len());
+        }
+        self.profile = profile;
+        // cached instructions were decoded under the old profile
+        self.decoded = DecodeCache::default();
+        self.record_settings();
+        self
+    }
+
+    pub fn with_arithmetic(mut self, policy: ArithmeticPolicy) -> Self {
+        self.arithmetic = policy;
+        self.record_settings();
+        self
+    }
+
+    /// Fails with [`VmError::ImmediateWrite`] on instructions that encode a
+    /// write parameter in immediate mode, instead of treating it as position
+    /// mode.
+    pub fn with_strict_decoding(mut self) -> Self {
+        self.strict = true;
+        self.decoded = DecodeCache::default();
+        self
+    }
+
+    /// Adds an instruction of its own to the ones the VM understands. These
+    /// aren't recorded by traces or counted by the profiler.
+    ///
+    /// # Panics
+    ///
+    /// If the extension's opcode isn't within 1 and 99, or is already taken by
+    /// a standard instruction or another extension.
+    pub fn with_extension(mut self, extension: Arc<dyn OpcodeExtension>) -> Self {
+        let code = extension.code();
+        assert!((1..=99).contains(&code), "opcode {} out of range", code);
+        assert!(
+            Opcode::try_from(code).is_err() && self.extensions.get(code).is_none(),
+            "opcode {} is already taken",
+            code
+        );
+
+        self.extensions.push(extension);
+        self
+    }
+
+    /// Maps `device` over `range`: reads and writes to those addresses, whether
+    /// from the program or through [`VM::read_mem`] and [`VM::write_mem`], go
+    /// to the device instead of memory.
+    ///
+    /// # Panics
+    ///
+    /// If `range` overlaps a device attached earlier.
+    pub fn with_device(mut self, range: Range<usize>, device: Arc<Mutex<dyn Device>>) -> Self {
+        assert!(
+            !self.devices.overlaps(&range),
+            "{:?} overlaps another device",
+            range
+        );
+
+        // code read from a device can change without being written to
+        self.decoded = DecodeCache::default();
+        self.devices.push(range, device);
+        self
+    }
+
+    /// Fails with [`VmError::InstructionLimit`] once `limit` instructions have
+    /// been executed.
+    pub fn with_instruction_limit(mut self, limit: u64) -> Self {
+        self.instruction_limit = Some(limit);
+        self
+    }
+
+    /// Fails with [`VmError::DeadlineExceeded`] if it is still running at
+    /// `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Fails with [`VmError::InfiniteLoop`] when the VM gets back to the exact
+    /// same `ip`, relative base and memory without any I/O or device access in
+    /// between, since from there it would repeat itself forever.
+    ///
+    /// Only one earlier state is kept to compare against, so memory use doesn't
+    /// grow with the length of the run, but a loop may go round a few times
+    /// before it's reported. Memory is compared whenever `ip` and the relative
+    /// base match a saved state, which is slow for programs with a lot of it.
+    pub fn with_loop_detection(mut self) -> Self {
+        self.loop_detector = Some(LoopDetector::default());
+        self
+    }
+
+    /// Records every executed instruction. See [`VM::take_trace`].
+    pub fn with_trace(mut self) -> Self {
+        self.tracer = Some(Tracer::default());
+        self.record_settings();
+        self
+    }
+
+    /// Counts executions per opcode and address, memory accesses per cell and
+    /// loop iterations. See [`VM::profiler`].
+    pub fn with_profiler(mut self) -> Self {
+        self.profiler = Some(Profiler::default());
+        self
+    }
+
+    /// Keeps an undo log of the last `capacity` executed instructions, so that
+    /// [`VM::step_back`] and [`VM::run_back_to`] can rewind them.
+    ///
+    /// Writes to devices and pokes through [`VM::write_mem`] happen outside
+    /// the program's memory, and aren't undone. Neither are traces or profiles.
+    pub fn with_history(mut self, capacity: usize) -> Self {
+        self.history = Some(History::new(capacity));
+        self
+    }
+
+    /// Fills the decode cache with every instruction reachable from the start of
+    /// the program. Worth it when one VM is cloned for many runs, so that the
+    /// clones don't each decode the program again.
+    pub fn predecoded(mut self) -> Self {
+        let program: Vec<i64> = (0..self.memory.len())
+            .map(|addr| self.memory.get(addr).unwrap_or(0))
+            .collect();
+
+        for addr in reachable_instructions(&program).into_keys() {
+            if self.devices.is_mapped(addr) {
+                continue;
+            }
+            if let Ok(instruction) = self.decode(program[addr]) {
+                self.decoded.insert(addr, instruction);
+            }
+        }
+
+        self
+    }
+
+    /// Stores the day 2 "noun" and "verb" in addresses 1 and 2.
+    pub fn with_noun_verb(mut self, noun: i64, verb: i64) -> Result<Self, VmError> {
+        self.write_mem(1, noun)?;
+        self.write_mem(2, verb)?;
+        Ok(self)
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn pending_input(&self) -> &VecDeque<i64> {
+        &self.input
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.tracer.as_ref().map(|x| &x.trace)
+    }
+
+    /// Hands over the steps recorded so far, and keeps recording from here on.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.tracer.as_mut().map(|x| Trace {
+            steps: std::mem::take(&mut x.trace.steps),
+            ..x.trace.clone()
+        })
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// How many instructions [`VM::step_back`] can undo.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    /// How many instructions have been executed so far.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Captures everything needed to pick the program up where it is now.
+    /// Limits, loop detection and tracing are settings of the run rather than
+    /// program state, so they aren't included.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ip: self.ip,
+            relative_base: self.relative_base,
+            profile: self.profile,
+            arithmetic: self.arithmetic,
+            memory_limit: self.memory.limit,
+            input: self.input.iter().copied().collect(),
+            memory_len: self.memory.len(),
+            memory: self.memory.segments(),
+            wide: self.memory.wide_cells(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Self, VmError> {
+        let mut vm = Self::new(&[]);
+        vm.restore(snapshot)?;
+        Ok(vm)
+    }
+
+    /// Puts the VM back in the state saved in `snapshot`, keeping what the
+    /// snapshot doesn't hold: extensions, devices, strict decoding, limits and
+    /// the tracer and profiler. The undo log from [`VM::with_history`] is
+    /// cleared, since it can't rewind across the jump.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), VmError> {
+        self.memory = Memory::from_segments(
+            snapshot.memory_len,
+            &snapshot.memory,
+            &snapshot.wide,
+            snapshot.memory_limit,
+        )
+        .map_err(|x| x.at(snapshot.ip, 0))?;
+        self.ip = snapshot.ip;
+        self.halted = false;
+        self.relative_base = snapshot.relative_base;
+        self.input = snapshot.input.into();
+        self.profile = snapshot.profile;
+        self.arithmetic = snapshot.arithmetic;
+        self.decoded = DecodeCache::default();
+        self.forget_seen_states();
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        self.record_settings();
+        Ok(())
+    }
+
+    pub fn run(
+        &mut self,
+        input: &mut impl IntcodeInput,
+        output: &mut impl IntcodeOutput,
+    ) -> Result<i64, VmError> {
+        loop {
+            match self.resume()? {
+                RunState::NeedsInput => match input.read().map_err(|x| self.io_error(x))? {
+                    Some(value) => self.push_input(value),
+                    None => {
+                        return Err(VmError::InputExhausted {
+                            ip: self.ip,
+                            instruction: self.word_at(self.ip),
+                        })
+                    }
+                },
+                RunState::Output(x) => output.write(x).map_err(|x| self.io_error(x))?,
+                RunState::Halted => break,
+            }
+        }
+
+        self.read_mem(0)
+    }
+
+    pub fn push_input(&mut self, value: i64) {
+        self.input.push_back(value);
+    }
+
+    /// Runs until the program outputs a value, needs input that hasn't been
+    /// pushed yet, or halts. Calling it again picks up where it stopped.
+    pub fn resume(&mut self) -> Result<RunState, VmError> {
+        loop {
+            if let Some(state) = self.step()? {
+                return Ok(state);
+            }
+        }
+    }
+
+    /// Executes a single instruction. Returns the state the VM stopped in if the
+    /// instruction produced output, needs input or halted.
+    pub fn step(&mut self) -> Result<Option<RunState>, VmError> {
+        if self.halted || self.ip >= self.memory.len() {
+            return Ok(Some(RunState::Halted));
+        }
+
+        let (ip, word) = (self.ip, self.read_mem(self.ip)?);
+        self.check_limits().map_err(|x| x.at(ip, word))?;
+
+        let (relative_base, memory_len) = (self.relative_base, self.memory.len());
+        self.remember(|x| x.begin(ip, relative_base, memory_len));
+        let result = self.exec(word).map_err(|x| x.at(ip, word));
+        if !self.devices.is_empty() && self.devices.take_accessed() {
+            self.forget_seen_states();
+        }
+        let executed = !matches!(result, Err(_) | Ok(Some(RunState::NeedsInput)));
+
+        if executed {
+            self.executed += 1;
+            self.halted = result == Ok(Some(RunState::Halted));
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            match executed {
+                true => tracer.commit(),
+                false => tracer.discard(),
+            }
+        }
+        if let Some(history) = self.history.as_mut() {
+            match executed {
+                true => history.commit(),
+                false => history.discard(),
+            }
+        }
+
+        result
+    }
+
+    /// Undoes the last executed instruction: restores the memory it wrote and
+    /// the registers, and puts back any input it consumed. Returns `false` if
+    /// there's nothing left to undo, or history is off.
+    pub fn step_back(&mut self) -> bool {
+        let Some(step) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+
+        for (addr, old) in step.writes.into_iter().rev() {
+            self.memory
+                .set_wide(addr, old)
+                .expect("undone writes were within the memory limit");
+            self.decoded.invalidate(addr);
+        }
+        self.memory.truncate(step.memory_len);
+
+        if let Some(value) = step.input {
+            self.input.push_front(value);
+        }
+        self.ip = step.ip;
+        self.halted = false;
+        self.relative_base = step.relative_base;
+        self.executed = self.executed.saturating_sub(1);
+        self.forget_seen_states();
+
+        true
+    }
+
+    /// Steps back until the instruction at `addr` is the next one to run.
+    /// Returns `false`, leaving the VM at the oldest step it remembers, if none
+    /// of the remembered steps ran from there.
+    pub fn run_back_to(&mut self, addr: usize) -> bool {
+        while self.step_back() {
+            if self.ip == addr {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Copies the settings that change how a program runs into the trace, so
+    /// that [`Trace::replay`] can run it the same way.
+    fn record_settings(&mut self) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace.profile = self.profile;
+            tracer.trace.arithmetic = self.arithmetic;
+            tracer.trace.memory_limit = self.memory.limit;
+        }
+    }
+
+    fn check_limits(&mut self) -> Result<(), VmError> {
+        if let Some(limit) = self.instruction_limit {
+            if self.executed >= limit {
+                return Err(VmError::InstructionLimit {
+                    ip: 0,
+                    instruction: 0,
+                    limit,
+                });
+            }
+        }
+
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(VmError::DeadlineExceeded {
+                    ip: 0,
+                    instruction: 0,
+                });
+            }
+        }
+
+        if let Some(detector) = self.loop_detector.as_mut() {
+            if detector.repeats(self.ip, self.relative_base, &self.memory) {
+                return Err(VmError::InfiniteLoop {
+                    ip: 0,
+                    instruction: 0,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Input and output change the world outside the VM, so a state seen before
+    /// them doesn't mean the program is stuck.
+    fn forget_seen_states(&mut self) {
+        if let Some(detector) = self.loop_detector.as_mut() {
+            *detector = LoopDetector::default();
+        }
+    }
+
+    fn exec(&mut self, word: i64) -> Result<Option<RunState>, VmError> {
+        let ip = self.ip;
+        if !self.extensions.is_empty() {
+            if let Some(extension) = self.extensions.get(word % 100) {
+                return self.exec_extension(extension.as_ref(), word);
+            }
+        }
+
+        let instruction = match self.decoded.get(ip) {
+            Some(instruction) => instruction,
+            None => {
+                let instruction = self.decode(word)?;
+                if !self.devices.is_mapped(ip) {
+                    self.decoded.insert(ip, instruction);
+                }
+                instruction
+            }
+        };
+        self.record(|x| x.begin(ip, word, instruction));
+        if instruction.opcode != Opcode::Input || !self.input.is_empty() {
+            self.count(|x| x.instruction(ip, instruction.opcode));
+        }
+
+        match instruction.opcode {
+            Opcode::Add => self.exec_add(instruction.modes)?,
+            Opcode::Mul => self.exec_mul(instruction.modes)?,
+            Opcode::Input => match self.pop_input() {
+                Some(value) => self.exec_input(value, instruction.modes)?,
+                None => return Ok(Some(RunState::NeedsInput)),
+            },
+            Opcode::Output => {
+                self.forget_seen_states();
+                let value = self.exec_output(instruction.modes)?;
+                self.record(|x| x.io(IoEvent::Output(value)));
+                return Ok(Some(RunState::Output(value)));
+            }
+            Opcode::JumpNotZero => self.exec_jump_not_zero(instruction.modes)?,
+            Opcode::JumpZero => self.exec_jump_zero(instruction.modes)?,
+            Opcode::Less => self.exec_less(instruction.modes)?,
+            Opcode::Equal => self.exec_equal(instruction.modes)?,
+            Opcode::AdjustRelativeBase => self.exec_adjust_relative_base(instruction.modes)?,
+            Opcode::Halt => return Ok(Some(RunState::Halted)),
+        }
+
+        Ok(None)
+    }
+
+    /// Takes the next input value, recording it for the trace and history.
+    pub(crate) fn pop_input(&mut self) -> Option<i64> {
+        self.forget_seen_states();
+        let value = self.input.pop_front()?;
+        self.record(|x| x.io(IoEvent::Input(value)));
+        self.remember(|x| x.input(value));
+        Some(value)
+    }
+
+    fn exec_extension(
+        &mut self,
+        extension: &dyn OpcodeExtension,
+        word: i64,
+    ) -> Result<Option<RunState>, VmError> {
+        let mut ctx = ExtensionContext::new(self, word);
+        let state = extension.exec(&mut ctx)?;
+        let jump = ctx.jump_target();
+
+        // an extension waiting for input runs again from the start
+        if state != Some(RunState::NeedsInput) {
+            self.ip = jump.unwrap_or(self.ip + 1 + extension.arity());
+        }
+        if state.is_some() {
+            self.forget_seen_states();
+        }
+        Ok(state)
+    }
+
+    fn decode(&self, word: i64) -> Result<Instruction, VmError> {
+        let instruction = match self.strict {
+            true => Instruction::decode_strict(word)?,
+            false => Instruction::try_from(word)?,
+        };
+        if self.profile == Profile::Day2 {
+            check_day2(word, instruction)?;
+        }
+
+        Ok(instruction)
+    }
+
+    pub fn read_mem(&self, addr: usize) -> Result<i64, VmError> {
+        match self.read_device(addr) {
+            Some(result) => result,
+            None => self.memory.get(addr).map_err(|x| x.at(self.ip, 0)),
+        }
+    }
+
+    /// Like [`VM::read_mem`], but also reads cells written under
+    /// [`ArithmeticPolicy::Wide`] that don't fit an `i64`.
+    pub fn read_wide(&self, addr: usize) -> Result<i128, VmError> {
+        match self.read_device(addr) {
+            Some(result) => result.map(i128::from),
+            None => self.memory.get_wide(addr).map_err(|x| x.at(self.ip, 0)),
+        }
+    }
+
+    pub fn write_mem(&mut self, addr: usize, value: i64) -> Result<(), VmError> {
+        // the caller may be patching the program to carry on
+        self.halted = false;
+        self.write_cell(addr, value.into())
+    }
+
+    fn read_device(&self, addr: usize) -> Option<Result<i64, VmError>> {
+        if self.devices.is_empty() {
+            return None;
+        }
+
+        self.devices
+            .read(addr)
+            .map(|result| result.map_err(|x| x.at(self.ip, 0)))
+    }
+
+    fn write_cell(&mut self, addr: usize, value: i128) -> Result<(), VmError> {
+        if !self.devices.is_empty() {
+            if let Some(result) = self.devices.write(addr, narrow(value)?) {
+                return result.map_err(|x| x.at(self.ip, 0));
+            }
+        }
+
+        self.memory
+            .set_wide(addr, value)
+            .map_err(|x| x.at(self.ip, 0))?;
+        self.decoded.invalidate(addr);
+        Ok(())
+    }
+
+    fn record(&mut self, event: impl FnOnce(&mut Tracer)) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            event(tracer);
+        }
+    }
+
+    fn remember(&mut self, event: impl FnOnce(&mut History)) {
+        if let Some(history) = self.history.as_mut() {
+            event(history);
+        }
+    }
+
+    fn count(&mut self, event: impl FnOnce(&mut Profiler)) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            event(profiler);
+        }
+    }
+
+    /// Writes the result of an instruction, as opposed to [`VM::write_mem`]
+    /// which pokes memory from outside the program.
+    pub(crate) fn store(&mut self, addr: usize, value: i128) -> Result<(), VmError> {
+        if self.history.is_some() && !self.devices.is_mapped(addr) {
+            let old = self.memory.get_wide(addr).map_err(|x| x.at(self.ip, 0))?;
+            self.remember(|x| x.write(addr, old));
+        }
+        self.write_cell(addr, value)?;
+        self.record(|x| x.write(addr, value));
+        self.count(|x| x.write(addr));
+        Ok(())
+    }
+
+    /// The word at `addr`, to describe an error. It's read straight from
+    /// memory, since reading a device again could have side effects, so a
+    /// device-mapped address reads as whatever memory holds underneath.
+    pub(crate) fn word_at(&self, addr: usize) -> i64 {
+        self.memory.get(addr).unwrap_or(0)
+    }
+
+    pub(crate) fn io_error(&self, error: io::Error) -> VmError {
+        VmError::Io {
+            ip: self.ip,
+            instruction: self.word_at(self.ip),
+            message: error.to_string(),
+        }
+    }
+
+    pub(crate) fn read_param(&mut self, offset: usize, mode: ParamMode) -> Result<i128, VmError> {
+        let addr = match mode {
+            ParamMode::Position => to_address(self.read_mem(self.ip + offset)?)?,
+            ParamMode::Immediate => return self.read_wide(self.ip + offset),
+            ParamMode::Relative => to_address(self.relative(self.read_mem(self.ip + offset)?)?)?,
+        };
+
+        self.count(|x| x.read(addr));
+        self.read_wide(addr)
+    }
+
+    pub(crate) fn write_param(&self, offset: usize, mode: ParamMode) -> Result<usize, VmError> {
+        let raw = self.read_mem(self.ip + offset)?;
+
+        match mode {
+            ParamMode::Relative => to_address(self.relative(raw)?),
+            _ => to_address(raw),
+        }
+    }
+
+    fn relative(&self, offset: i64) -> Result<i64, VmError> {
+        self.relative_base
+            .checked_add(offset)
+            .ok_or(VmError::overflow())
+    }
+
+    fn read_params2(&mut self, modes: [ParamMode; 3]) -> Result<(i128, i128), VmError> {
+        let (x, y) = (self.read_param(1, modes[0])?, self.read_param(2, modes[1])?);
+        self.record(|t| {
+            t.operand(x);
+            t.operand(y);
+        });
+        Ok((x, y))
+    }
+
+    fn read_params1(&mut self, modes: [ParamMode; 3]) -> Result<i128, VmError> {
+        let x = self.read_param(1, modes[0])?;
+        self.record(|t| t.operand(x));
+        Ok(x)
+    }
+
+    fn exec_add(&mut self, modes: [ParamMode; 3]) -> Result<(), VmError> {
+        let (x, y) = self.read_params2(modes)?;
+        let z = self.write_param(3, modes[2])?;
+
+        let sum = self.arithmetic.apply(x.checked_add(y))?;
+        self.store(z, sum)?;
+
+        self.ip += 4;
+        Ok(())
+    }
+
+    fn exec_mul(&mut self, modes: [ParamMode; 3]) -> Result<(), VmError> {
+        let (x, y) = self.read_params2(modes)?;
+        let z = self.write_param(3, modes[2])?;
+
+        let mul = self.arithmetic.apply(x.checked_mul(y))?;
+        self.store(z, mul)?;
+
+        self.ip += 4;
+        Ok(())
+    }
+
+    fn exec_output(&mut self, modes: [ParamMode; 3]) -> Result<i64, VmError> {
+        let x = narrow(self.read_params1(modes)?)?;
+
+        self.ip += 2;
+        Ok(x)
+    }
+
+    fn exec_input(&mut self, value: i64, modes: [ParamMode; 3]) -> Result<(), VmError> {
+        let x = self.write_param(1, modes[0])?;
+
+        self.store(x, value.into())?;
+
+        self.ip += 2;
+        Ok(())
+    }
+
+    fn exec_jump_not_zero(&mut self, modes: [ParamMode; 3]) -> Result<(), VmError> {
+        let (x, addr) = self.read_params2(modes)?;
+        let from = self.ip;
+        self.ip += 3;
+
+        if x != 0 {
+            self.jump(from, addr)?;
+        }
+
+        Ok(())
+    }
+
+    fn exec_jump_zero(&mut self, modes: [ParamMode; 3]) -> Result<(), VmError> {
+        let (x, addr) = self.read_params2(modes)?;
+        let from = self.ip;
+        self.ip += 3;
+
+        if x == 0 {
+            self.jump(from, addr)?;
+        }
+
+        Ok(())
+    }
+
+    fn jump(&mut self, from: usize, target: i128) -> Result<(), VmError> {
+        let to = to_address(narrow(target)?)?;
+        self.ip = to;
+        self.count(|x| x.jump(from, to));
+        Ok(())
+    }
+
+    fn exec_less(&mut self, modes: [ParamMode; 3]) -> Result<(), VmError> {
+        let (x, y) = self.read_params2(modes)?;
+        let addr = self.write_param(3, modes[2])?;
+        self.store(addr, (x < y) as i128)?;
+
+        self.ip += 4;
+        Ok(())
+    }
+
+    fn exec_equal(&mut self, modes: [ParamMode; 3]) -> Result<(), VmError> {
+        let (x, y) = self.read_params2(modes)?;
+        let addr = self.write_param(3, modes[2])?;
+        self.store(addr, (x == y) as i128)?;
+
+        self.ip += 4;
+        Ok(())
+    }
+
+    fn exec_adjust_relative_base(&mut self, modes: [ParamMode; 3]) -> Result<(), VmError> {
+        let x = narrow(self.read_params1(modes)?)?;
+        self.relative_base = self.relative(x)?;
+
+        self.ip += 2;
+        Ok(())
+    }
+}
+
+fn narrow(value: i128) -> Result<i64, VmError> {
+    i64::try_from(value).map_err(|_| VmError::overflow())
+}
+
+pub(crate) fn check_day2(word: i64, instruction: Instruction) -> Result<(), VmError> {
+    if !matches!(instruction.opcode, Opcode::Add | Opcode::Mul | Opcode::Halt) {
+        return Err(VmError::UnknownOpcode {
+            ip: 0,
+            instruction: word,
+            opcode: instruction.opcode.code(),
+        });
+    }
+
+    if word / 100 == 0 {
+        return Ok(());
+    }
+
+    // report the first parameter that isn't in position mode, or the digits
+    // past the last one if that's where the mode is
+    let mode = (0..3)
+        .map(|i| raw_mode(word, i))
+        .find(|x| *x != 0)
+        .unwrap_or(word / 100_000);
+    Err(VmError::BadMode {
+        ip: 0,
+        instruction: word,
+        mode,
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::useless_vec)]
+mod tests {
+    use super::super::{TextInput, TextOutput};
+    use super::*;
+
+    fn run(src: &[i64] /* input: &mut impl io::BufRe, output: &mut impl io::Write */) -> i64 {
+        // io::stdin.lock()
+        // io::stdout()
+        run_with_buffers(src, "", &mut vec![])
+    }
+
+    fn run_with_buffers(src: &[i64], input: &str, output: &mut impl io::Write) -> i64 {
+        let mut vm = VM::new(src);
+        vm.run(&mut TextInput(input.as_bytes()), &mut TextOutput(output))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_run_day02() {
+        assert_eq!(run(&vec![1, 0, 0, 0, 99]), 2);
+        assert_eq!(run(&vec![2, 3, 0, 3, 99]), 2);
+        assert_eq!(run(&vec![2, 4, 4, 5, 99, 0]), 2);
+        assert_eq!(run(&vec![1, 1, 1, 4, 99, 5, 6, 0, 99]), 30);
+    }
+
+    #[test]
+    fn test_run_with_modes() {
+        assert_eq!(run(&vec![1101, 100, -1, 0]), 99);
+    }
+
+    #[test]
+    fn test_output_in_buffer() {
+        let mut buffer = vec![];
+        run_with_buffers(&vec![4, 0], "", &mut buffer);
+        assert_eq!(String::from_utf8(buffer).unwrap(), "4\n");
+    }
+
+    #[test]
+    fn test_input_in_buffer() {
+        let mut buffer = vec![];
+        assert_eq!(
+            run_with_buffers(&vec![3, 2, 0, 1, 1, 0], "1101", &mut buffer),
+            2
+        );
+    }
+
+    #[test]
+    fn test_jumps_with_position_mode() {
+        // 3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9
+        let mut buffer = vec![];
+        run_with_buffers(
+            &vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9],
+            "0",
+            &mut buffer,
+        );
+        assert_eq!(String::from_utf8(buffer).unwrap(), "0\n");
+
+        let mut buffer2 = vec![];
+        run_with_buffers(
+            &vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9],
+            "8",
+            &mut buffer2,
+        );
+        assert_eq!(String::from_utf8(buffer2).unwrap(), "1\n");
+    }
+
+    #[test]
+    fn test_jumps_with_immediate_mode() {
+        let mut buffer = vec![];
+        run_with_buffers(
+            &vec![3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+            "0",
+            &mut buffer,
+        );
+        assert_eq!(String::from_utf8(buffer).unwrap(), "0\n");
+
+        let mut buffer2 = vec![];
+        run_with_buffers(
+            &vec![3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+            "8",
+            &mut buffer2,
+        );
+        assert_eq!(String::from_utf8(buffer2).unwrap(), "1\n");
+    }
+
+    #[test]
+    fn test_equals_with_position_mode() {
+        let mut buffer = vec![];
+        run_with_buffers(&vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8], "8", &mut buffer);
+        assert_eq!(String::from_utf8(buffer).unwrap(), "1\n");
+
+        let mut buffer2 = vec![];
+        run_with_buffers(
+            &vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8],
+            "-8",
+            &mut buffer2,
+        );
+        assert_eq!(String::from_utf8(buffer2).unwrap(), "0\n");
+    }
+
+    #[test]
+    fn test_less_with_immediate_mode() {
+        let mut buffer1 = vec![];
+        run_with_buffers(&vec![3, 3, 1107, -1, 8, 3, 4, 3, 99], "7", &mut buffer1);
+        assert_eq!(String::from_utf8(buffer1).unwrap(), "1\n");
+
+        let mut buffer2 = vec![];
+        run_with_buffers(&vec![3, 3, 1107, -1, 8, 3, 4, 3, 99], "8", &mut buffer2);
+        assert_eq!(String::from_utf8(buffer2).unwrap(), "0\n");
+    }
+
+    #[test]
+    fn test_adjust_relative_base() {
+        let mut buffer = vec![];
+        run_with_buffers(&[109, 6, 204, -1, 99, 42], "", &mut buffer);
+        assert_eq!(String::from_utf8(buffer).unwrap(), "42\n");
+    }
+
+    #[test]
+    fn test_write_with_relative_mode() {
+        assert_eq!(run(&[109, 2, 21101, 3, 4, -2, 99]), 7);
+
+        let mut buffer = vec![];
+        run_with_buffers(&[109, 7, 203, 0, 204, 0, 99, 0], "13", &mut buffer);
+        assert_eq!(String::from_utf8(buffer).unwrap(), "13\n");
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut vm = VM::new(&[1101, 1, 2, 100, 99]).with_memory_limit(64);
+        assert_eq!(
+            vm.run(&mut VecDeque::new(), &mut vec![]),
+            Err(VmError::OutOfBounds {
+                ip: 0,
+                instruction: 1101,
+                address: 100
+            })
+        );
+
+        let mut vm = VM::new(&[1101, 1, 2, 10, 99]).with_memory_limit(64);
+        assert!(vm.run(&mut VecDeque::new(), &mut vec![]).is_ok());
+        assert_eq!(vm.read_mem(10), Ok(3));
+    }
+
+    #[test]
+    fn test_run_with_large_memory() {
+        let mut buffer = vec![];
+        let src = [
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        run_with_buffers(&src, "", &mut buffer);
+
+        let expected: Vec<String> = src.iter().map(|x| format!("{}\n", x)).collect();
+        assert_eq!(String::from_utf8(buffer).unwrap(), expected.concat());
+
+        assert_eq!(run(&[1101, 2, 3, 1_000_000, 4, 1_000_000, 99]), 1101);
+    }
+
+    #[test]
+    fn test_negative_address() {
+        let mut vm = VM::new(&[4, -1, 99]);
+        assert_eq!(
+            vm.run(&mut VecDeque::new(), &mut vec![]),
+            Err(VmError::NegativeAddress {
+                ip: 0,
+                instruction: 4,
+                address: -1
+            })
+        );
+    }
+
+    #[test]
+    fn test_resume_pauses_on_io() {
+        let mut vm = VM::new(&[3, 0, 4, 0, 3, 0, 4, 0, 99]);
+
+        assert_eq!(vm.resume(), Ok(RunState::NeedsInput));
+        assert_eq!(vm.resume(), Ok(RunState::NeedsInput));
+        vm.push_input(5);
+        assert_eq!(vm.resume(), Ok(RunState::Output(5)));
+        assert_eq!(vm.resume(), Ok(RunState::NeedsInput));
+        vm.push_input(6);
+        assert_eq!(vm.resume(), Ok(RunState::Output(6)));
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn test_resume_with_queued_input() {
+        let mut vm = VM::new(&[3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0]);
+        vm.push_input(3);
+        vm.push_input(4);
+
+        assert_eq!(vm.resume(), Ok(RunState::Output(7)));
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn test_resume_after_running_off_memory() {
+        let mut vm = VM::new(&[1101, 100, -1, 0]);
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+        assert_eq!(vm.read_mem(0), Ok(99));
+    }
+
+    #[test]
+    fn test_text_input_reads_one_value_per_line() {
+        let mut buffer = vec![];
+        run_with_buffers(&[3, 0, 3, 1, 4, 0, 4, 1, 99], "3\n\n-4\n", &mut buffer);
+        assert_eq!(String::from_utf8(buffer).unwrap(), "3\n-4\n");
+    }
+
+    #[test]
+    fn test_run_with_exhausted_input() {
+        let mut vm = VM::new(&[3, 0, 3, 1, 99]);
+        assert_eq!(
+            vm.run(&mut VecDeque::from([1]), &mut vec![]),
+            Err(VmError::InputExhausted {
+                ip: 2,
+                instruction: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut vm = VM::new(&[1101, 1, 1, 0, 42]);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::UnknownOpcode {
+                ip: 4,
+                instruction: 42,
+                opcode: 42
+            })
+        );
+
+        let mut vm = VM::new(&[1101, 1, 1, 0, 304, 0]);
+        let error = vm.resume().unwrap_err();
+        assert_eq!(
+            error,
+            VmError::BadMode {
+                ip: 4,
+                instruction: 304,
+                mode: 3
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "unrecognized param mode 3 (instruction 304 at 4)"
+        );
+    }
+
+    #[test]
+    fn test_strict_decoding() {
+        let src = [11101, 1, 2, 5, 99, 0];
+        assert_eq!(run(&src), 11101);
+
+        let mut vm = VM::new(&src).with_strict_decoding();
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::ImmediateWrite {
+                ip: 0,
+                instruction: 11101,
+                param: 3
+            })
+        );
+
+        let mut vm = VM::new(&[21101, 1, 2, 5, 99, 0]).with_strict_decoding();
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+        assert_eq!(vm.read_mem(5), Ok(3));
+    }
+
+    #[test]
+    fn test_arithmetic_overflow() {
+        let mut vm = VM::new(&[1102, i64::MAX, 2, 0, 99]);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::Overflow {
+                ip: 0,
+                instruction: 1102
+            })
+        );
+
+        let mut vm = VM::new(&[109, i64::MAX, 109, 1, 99]);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::Overflow {
+                ip: 2,
+                instruction: 109
+            })
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_policies() {
+        let program = [1102, i64::MAX, 2, 5, 99, 0];
+        let run = |policy| {
+            let mut vm = VM::new(&program).with_arithmetic(policy);
+            vm.resume().map(|_| vm.read_wide(5).unwrap())
+        };
+
+        assert!(matches!(
+            run(ArithmeticPolicy::Checked),
+            Err(VmError::Overflow { ip: 0, .. })
+        ));
+        assert_eq!(run(ArithmeticPolicy::Wrapping), Ok(-2));
+        assert_eq!(run(ArithmeticPolicy::Saturating), Ok(i64::MAX as i128));
+        assert_eq!(run(ArithmeticPolicy::Wide), Ok(i64::MAX as i128 * 2));
+
+        let mut vm = VM::new(&program).with_arithmetic(ArithmeticPolicy::Saturating);
+        vm.write_mem(2, -2).unwrap();
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+        assert_eq!(vm.read_mem(5), Ok(i64::MIN));
+    }
+
+    #[test]
+    fn test_wide_cells() {
+        // squares i64::MAX, then copies the square with an add
+        let src = [
+            1002,
+            13,
+            1,
+            14,
+            2,
+            14,
+            14,
+            15,
+            1,
+            15,
+            16,
+            17,
+            99,
+            i64::MAX,
+            0,
+            0,
+            0,
+            0,
+        ];
+        let mut vm = VM::new(&src).with_arithmetic(ArithmeticPolicy::Wide);
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+        assert_eq!(vm.read_wide(17), Ok(i64::MAX as i128 * i64::MAX as i128));
+        assert!(vm.read_mem(17).is_err());
+
+        // a wide value can't leave the VM
+        let mut vm =
+            VM::new(&[1002, 7, 2, 7, 4, 7, 99, i64::MAX]).with_arithmetic(ArithmeticPolicy::Wide);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::Overflow {
+                ip: 4,
+                instruction: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // adds 5 to [30], then rewrites itself to multiply by 5 and runs again
+        let src = [
+            1001, 30, 5, 30, 1006, 31, 12, 99, 0, 0, 0, 0, 1101, 1002, 0, 0, 1101, 1, 0, 31, 1105,
+            1, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0,
+        ];
+        let mut vm = VM::new(&src);
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+        assert_eq!(vm.read_mem(30), Ok(35));
+
+        // a cached instruction is dropped when its word is overwritten
+        let mut vm = VM::new(&[1101, 1, 1, 20, 1105, 1, 0, 99]);
+        assert_eq!(vm.step(), Ok(None));
+        assert_eq!(vm.step(), Ok(None));
+        vm.write_mem(0, 1102).unwrap();
+        assert_eq!(vm.step(), Ok(None));
+        assert_eq!(vm.read_mem(20), Ok(1));
+    }
+
+    #[test]
+    fn test_predecoded() {
+        let src = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let vm = VM::new(&src).with_profile(Profile::Day2).predecoded();
+        assert_eq!(vm.decoded.get(4).map(|x| x.opcode), Some(Opcode::Mul));
+        assert_eq!(vm.decoded.get(9), None);
+
+        let mut run = vm.clone();
+        assert_eq!(run.run(&mut VecDeque::new(), &mut vec![]), Ok(3500));
+        assert_eq!(vm.read_mem(0), Ok(1));
+    }
+
+    #[test]
+    fn test_predecoded_then_reconfigured() {
+        let mut vm = VM::new(&[11101, 1, 2, 5, 99, 0])
+            .predecoded()
+            .with_strict_decoding();
+        assert!(matches!(
+            vm.resume(),
+            Err(VmError::ImmediateWrite { ip: 0, .. })
+        ));
+
+        let mut vm = VM::new(&[104, 1, 99])
+            .predecoded()
+            .with_profile(Profile::Day2);
+        assert!(matches!(
+            vm.resume(),
+            Err(VmError::UnknownOpcode { ip: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        let mut vm = VM::new(&[1105, 1, 0]).with_instruction_limit(10);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::InstructionLimit {
+                ip: 0,
+                instruction: 1105,
+                limit: 10
+            })
+        );
+        assert_eq!(vm.executed(), 10);
+
+        let mut vm = VM::new(&[1101, 1, 1, 0, 99]).with_instruction_limit(2);
+        assert_eq!(vm.resume(), Ok(RunState::Halted));
+    }
+
+    #[test]
+    fn test_resume_after_halting() {
+        let mut vm = VM::new(&[99]).with_loop_detection().with_history(10);
+        for _ in 0..3 {
+            assert_eq!(vm.resume(), Ok(RunState::Halted));
+        }
+        assert_eq!(vm.executed(), 1);
+        assert!(vm.step_back());
+        assert!(!vm.step_back());
+
+        let mut vm = VM::new(&[99]).with_instruction_limit(3);
+        for _ in 0..5 {
+            assert_eq!(vm.resume(), Ok(RunState::Halted));
+        }
+        assert_eq!(vm.executed(), 1);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut vm = VM::new(&[1105, 1, 0]).with_deadline(Instant::now());
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::DeadlineExceeded {
+                ip: 0,
+                instruction: 1105
+            })
+        );
+    }
+
+    #[test]
+    fn test_loop_detection() {
+        let mut vm = VM::new(&[1105, 1, 3, 1105, 1, 0]).with_loop_detection();
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::InfiniteLoop {
+                ip: 0,
+                instruction: 1105
+            })
+        );
+        assert_eq!(vm.executed(), 2);
+
+        // counts down from 5, then spins on the jump at 7
+        let mut vm = VM::new(&[1001, 10, -1, 10, 1005, 10, 0, 1105, 1, 7, 5]).with_loop_detection();
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::InfiniteLoop {
+                ip: 7,
+                instruction: 1105
+            })
+        );
+
+        // a counter keeps changing memory, so it never repeats a state
+        let mut vm = VM::new(&[1001, 7, 1, 7, 1105, 1, 0, 0])
+            .with_loop_detection()
+            .with_instruction_limit(1_000);
+        assert!(matches!(vm.resume(), Err(VmError::InstructionLimit { .. })));
+
+        // neither does a loop that echoes its input
+        let mut vm = VM::new(&[3, 7, 4, 7, 1105, 1, 0, 0]).with_loop_detection();
+        for x in 0..3 {
+            assert_eq!(vm.resume(), Ok(RunState::NeedsInput));
+            vm.push_input(7);
+            assert_eq!(vm.resume(), Ok(RunState::Output(7)), "iteration {}", x);
+        }
+    }
+
+    #[test]
+    fn test_day2_profile() {
+        let mut vm = VM::new(&[1, 0, 0, 0, 99]).with_profile(Profile::Day2);
+        assert_eq!(vm.run(&mut VecDeque::new(), &mut vec![]), Ok(2));
+
+        let mut vm = VM::new(&[1, 0, 0, 5, 99]).with_profile(Profile::Day2);
+        assert_eq!(
+            vm.run(&mut VecDeque::new(), &mut vec![]),
+            Err(VmError::OutOfBounds {
+                ip: 0,
+                instruction: 1,
+                address: 5
+            })
+        );
+
+        let mut vm = VM::new(&[4, 0, 99]).with_profile(Profile::Day2);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::UnknownOpcode {
+                ip: 0,
+                instruction: 4,
+                opcode: 4
+            })
+        );
+
+        let mut vm = VM::new(&[1101, 1, 1, 0, 99]).with_profile(Profile::Day2);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::BadMode {
+                ip: 0,
+                instruction: 1101,
+                mode: 1
+            })
+        );
+
+        let mut vm = VM::new(&[1001, 0, 1, 0, 99]).with_profile(Profile::Day2);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::BadMode {
+                ip: 0,
+                instruction: 1001,
+                mode: 1
+            })
+        );
+        let mut vm = VM::new(&[20001, 0, 1, 0, 99]).with_profile(Profile::Day2);
+        assert_eq!(
+            vm.resume(),
+            Err(VmError::BadMode {
+                ip: 0,
+                instruction: 20001,
+                mode: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_with_noun_verb() {
+        let vm = VM::new(&[1, 0, 0, 0, 99])
+            .with_profile(Profile::Day2)
+            .with_noun_verb(3, 4)
+            .unwrap();
+        assert_eq!(vm.read_mem(1), Ok(3));
+        assert_eq!(vm.read_mem(2), Ok(4));
+
+        assert!(VM::new(&[99])
+            .with_profile(Profile::Day2)
+            .with_noun_verb(3, 4)
+            .is_err());
+    }
+}
@@ -6,5 +6,6 @@ pub mod day03;
 pub mod day04;
 pub mod day05;
 pub mod day06;
+pub mod intcode;
 
 aoc_lib! {year = 2019}